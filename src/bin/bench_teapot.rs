@@ -1,8 +1,12 @@
 use simplelog::{ConfigBuilder, LevelFilter, TermLogger, TerminalMode};
-use threed2vox::config::{Config, VoxelOption};
+use threed2vox::blocks::{BlockRegistry, BlockState};
+use threed2vox::config::Config;
 use threed2vox::nbtifier::SchematicV2;
+use threed2vox::preview::View;
 use threed2vox::readers::obj::ObjReader;
 use threed2vox::to_schematic;
+use threed2vox::version::MinecraftVersion;
+use threed2vox::voxelizer::VoxelOption;
 
 fn main() {
     let config = ConfigBuilder::new()
@@ -12,14 +16,39 @@ fn main() {
 
     let config = Config {
         voxel_size: VoxelOption::MeshSize(20.0),
-        data_version: 2566,
+        version: MinecraftVersion::from_data_version(2566),
         input_path: "models/teapot.obj".to_string(),
+        scene: None,
         filename: "teapot".to_string(),
-        block: "stone".to_string(),
-        x_rot: 0.0,
-        y_rot: 0.0,
-        z_rot: 0.0,
+        block: BlockState::new("stone"),
+        block_mix: None,
+        height_rule: None,
+        blocks: BlockRegistry::embedded(),
+        orient: true,
+        smooth: false,
+        combine: Vec::new(),
+        morphology: Vec::new(),
+        connectivity: Default::default(),
+        min_component_size: None,
+        keep_largest: false,
+        report_floating: false,
+        hollow: None,
+        water_parts: Vec::new(),
+        water_level: None,
+        max_overhang: None,
+        report_path: None,
+        materials_path: None,
+        materials_by_layer: false,
+        guide_path: None,
+        preview_path: None,
+        preview_view: View::Isometric,
+        transform: Default::default(),
+        up_axis: None,
+        handedness: None,
+        metres_per_unit: None,
         threads: 4,
+        progress: None,
+        cancellation: None,
         nbtify: Box::new(SchematicV2),
         reader: Box::new(ObjReader),
     };
@@ -1,11 +1,17 @@
 use std::env;
 use std::fs::File;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
 
-use clap::{App, Arg};
+use anyhow::Context;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use simplelog::*;
+use threed2vox::blocks::BlockState;
 use threed2vox::config::Config as AppConfig;
-use threed2vox::to_schematic;
+use threed2vox::nbtifier::read_schematic;
+use threed2vox::slice::{render_slice, slice_count, Legend};
+use threed2vox::transform::Axis;
+use threed2vox::{to_schematic, to_voxel_grid};
 
 fn main() -> anyhow::Result<()> {
     let config = ConfigBuilder::new()
@@ -17,36 +23,146 @@ fn main() -> anyhow::Result<()> {
         .version("1.0")
         .author("Sky")
         .about("Converts 3D files to Minecraft .schematic format")
-        .arg(Arg::with_name("input")
-            .help("Designate the input file")
-            .index(1)
-            .takes_value(true)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&conversion_args(true))
+        .subcommand(SubCommand::with_name("view")
+            .about("Prints slices of the output, or of an existing .schem or .nbt file, as text in the terminal. Step through them with the keys shown, or pick one with --layer.")
+            .args(&conversion_args(false))
+            .arg(Arg::with_name("axis")
+                .long("axis")
+                .help("The axis to slice along: y for layers seen from above, z for slices seen from the south, or x for slices seen from the east.")
+                .takes_value(true)
+                .possible_values(&["x", "y", "z"])
+                .default_value("y")
+            )
+            .arg(Arg::with_name("layer")
+                .long("layer")
+                .short("l")
+                .help("Print the slice at this coordinate along the axis and exit, instead of stepping through the slices.")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("all")
+                .long("all")
+                .help("Print every slice along the axis, one after another.")
+                .conflicts_with("layer")
+            )
+            .arg(Arg::with_name("no_colour")
+                .long("no-colour")
+                .alias("no-color")
+                .help("Draw blocks with letters only, without ANSI colours. Colours are also off when the output isn't a terminal or NO_COLOR is set.")
+            )
         )
-        .arg(Arg::with_name("output")
+        .get_matches_from(env::args());
+
+    if let Some(matches) = matches.subcommand_matches("view") {
+        return view(matches);
+    }
+
+    let path_prefix = matches
+        .value_of("output")
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    let path_prefix = Path::new(&path_prefix);
+
+    let config = AppConfig::from_argmatch(matches)?;
+    spawn_threads(&config)?;
+
+    let file_stem = config.filename.clone();
+    let file_ending = config.nbtify.file_ending();
+    let nbt = to_schematic(config)?;
+
+    // Output nbt to file.
+
+    std::fs::create_dir_all(path_prefix)?;
+    let output_path = path_prefix.join(format!("{}.{}", file_stem, file_ending));
+    log::info!("Writing to '{}'", output_path.to_str().unwrap());
+
+    let mut file = File::create(&output_path)
+        .with_context(|| format!("Could not create file {:?}", output_path))?;
+
+    nbt.to_gzip_writer(&mut file)?;
+
+    Ok(())
+}
+
+/// The options for converting a model, shared by the conversion itself and the view subcommand, which only needs a
+/// version for models and not for schematics.
+fn conversion_args(require_version: bool) -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("input")
+            .help("Designate the input file, either a model or a .toml scene manifest of several models. The view subcommand also accepts .schem and .nbt files.")
+            .index(1)
+            .takes_value(true),
+        Arg::with_name("output")
                 .short("o")
                 .long("output")
                 .help("Designate the output file")
-                .takes_value(true))
-        .arg(Arg::with_name("max_size")
+                .takes_value(true),
+        Arg::with_name("max_size")
             .short("s")
             .long("size")
             .help("Designate how many blocks long the longest axis of the model is. Defaults to using 1 model unit = 1 block, and is overridden by --scale.")
-            .overrides_with("scale")
-            .takes_value(true)
-        )
-        .arg(Arg::with_name("scale")
+            .overrides_with_all(&["scale", "width", "height", "length", "fit", "real_scale"])
+            .takes_value(true),
+        Arg::with_name("scale")
             .short("S")
             .long("scale")
             .help("Specify a units-to-blocks ratio. Defaults to 1, and is overridden by --size.")
             .takes_value(true)
-            .overrides_with("max_size")
-        )
-        .arg(Arg::with_name("block")
+            .overrides_with_all(&["max_size", "width", "height", "length", "fit", "real_scale"]),
+        Arg::with_name("width")
+            .long("width")
+            .help("Designate how many blocks long the model is along the X axis.")
+            .takes_value(true)
+            .overrides_with_all(&["max_size", "scale", "height", "length", "fit", "real_scale"]),
+        Arg::with_name("height")
+            .long("height")
+            .help("Designate how many blocks tall the model is.")
+            .takes_value(true)
+            .overrides_with_all(&["max_size", "scale", "width", "length", "fit", "real_scale"]),
+        Arg::with_name("length")
+            .long("length")
+            .help("Designate how many blocks long the model is along the Z axis.")
+            .takes_value(true)
+            .overrides_with_all(&["max_size", "scale", "width", "height", "fit", "real_scale"]),
+        Arg::with_name("fit")
+            .long("fit")
+            .help("Scale the model to the largest size that fits in a box of X,Y,Z blocks.")
+            .takes_value(true)
+            .overrides_with_all(&["max_size", "scale", "width", "height", "length", "real_scale"]),
+        Arg::with_name("real_scale")
+            .long("real-scale")
+            .help("Scale the model to its real-world size, given the number of blocks per metre. glTF is always in metres and COLLADA records its units, for other formats use --units.")
+            .takes_value(true)
+            .overrides_with_all(&["max_size", "scale", "width", "height", "length", "fit"]),
+        Arg::with_name("units")
+            .long("units")
+            .help("The length of one model unit for --real-scale, either as mm, cm, m, in, ft or a number of metres. Overrides the units recorded in the file.")
+            .takes_value(true),
+        Arg::with_name("block")
             .short("b")
             .long("block")
-            .help("The block id string to use for the shell of the model. Defaults to stone.")
-        )
-        .arg(Arg::with_name("minecraft version")
+            .help("The block id string to use for the shell of the model, e.g. stone, minecraft:oak_planks or oak_log[axis=y]. Defaults to stone. \
+Also accepts a weighted mix of blocks, e.g. stone:60,andesite:25,cobblestone:15.")
+            .takes_value(true),
+        Arg::with_name("bands")
+            .long("bands")
+            .help("Pick blocks by height, as bands starting at a Y coordinate, e.g. 0=deepslate;16=stone;60=snow_block. Each band can be a weighted mix.")
+            .takes_value(true)
+            .conflicts_with("gradient"),
+        Arg::with_name("gradient")
+            .long("gradient")
+            .help("Pick blocks by height, blending from the bottom of the model to the top, e.g. deepslate;stone;andesite;snow_block. Each step can be a weighted mix.")
+            .takes_value(true),
+        Arg::with_name("seed")
+            .long("seed")
+            .help("The seed for picking blocks from a mix given to --block. The same seed always gives the same result. Defaults to 0.")
+            .takes_value(true),
+        Arg::with_name("patch_size")
+            .long("patch-size")
+            .help("Group the blocks of a mix into patches about this many blocks across, instead of picking every block at random.")
+            .takes_value(true),
+        Arg::with_name("minecraft version")
             .short("V")
             .long("version")
             .help("Either the version (as a string) or the dataversion of Minecraft to make the schematic for.\
@@ -55,70 +171,247 @@ Another alternative is to specify 'none' version, though this is undefined behav
 \
 The largest difference between versions is pre- and post-1.13 (1241 vs 1626): the two use different schematic formats.")
             .takes_value(true)
-            .required(true)
-        )
-        .arg(Arg::with_name("x_rot")
+            .required(require_version),
+        Arg::with_name("versions_file")
+            .long("versions-file")
+            .help("A TOML file of version names to DataVersions, e.g. \"1.21.9\" = 4554, which adds to or overrides the built-in table.")
+            .takes_value(true),
+        Arg::with_name("strict_version")
+            .long("strict-version")
+            .help("Fail if the version name isn't in the version table, instead of using the closest match."),
+        Arg::with_name("allow_unknown_blocks")
+            .long("allow-unknown-blocks")
+            .help("Only warn about blocks that don't exist in the chosen version, instead of failing."),
+        Arg::with_name("blocks_file")
+            .long("blocks-file")
+            .help("A TOML file of extra blocks, in the same format as blocks.toml, which adds to or overrides the built-in registry.")
+            .takes_value(true),
+        Arg::with_name("no_orient")
+            .long("no-orient")
+            .help("Don't turn directional blocks like logs, observers or glazed terracotta to follow the model's surface."),
+        Arg::with_name("smooth")
+            .long("smooth")
+            .help("Use stairs and slabs of the block's material on sloped surfaces, e.g. stone_brick_stairs for stone_bricks."),
+        Arg::with_name("combine")
+            .long("combine")
+            .help("Voxelise another model with the same scale and transform, and combine it with the input as OPERATION:FILE, e.g. subtract:door.obj. The operation is union, intersect, subtract or xor. Both models are filled in solid first. Can be given more than once, and is applied in order.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("morph")
+            .long("morph")
+            .help("Dilate, erode, open or close the model after voxelising, as OPERATION[:SHAPE[:RADIUS]], e.g. close or dilate:sphere:2. The shape is cross, cube or sphere, and defaults to cube:1. Can be given more than once, and is applied in order.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("min_component_size")
+            .long("min-component-size")
+            .help("Remove separate pieces of the model with fewer voxels than this, e.g. stray blocks from scanned models.")
+            .takes_value(true),
+        Arg::with_name("keep_largest")
+            .long("keep-largest")
+            .help("Remove every separate piece of the model but the largest one."),
+        Arg::with_name("report_floating")
+            .long("report-floating")
+            .help("Warn about separate pieces of the model that don't reach the ground."),
+        Arg::with_name("connectivity")
+            .long("connectivity")
+            .help("Whether voxels are connected through their 6 faces, or also through their edges and corners (26). Defaults to 26.")
+            .takes_value(true)
+            .possible_values(&["6", "26"]),
+        Arg::with_name("hollow")
+            .long("hollow")
+            .help("Fill the model in and hollow it out again to walls this many blocks thick, leaving pillars inside to hold up the roof.")
+            .takes_value(true),
+        Arg::with_name("support_spacing")
+            .long("support-spacing")
+            .help("How many blocks apart the pillars left by --hollow are along X and Z, or 0 for no pillars. Defaults to 8.")
+            .takes_value(true)
+            .requires("hollow"),
+        Arg::with_name("water")
+            .long("water")
+            .help("Fill the parts of the model with this object or material name with water, e.g. water. Can be given more than once.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("water_level")
+            .long("water-level")
+            .help("Fill empty voxels below this Y coordinate of the output with water, where the model encloses them, e.g. inside a ship's hull.")
+            .takes_value(true),
+        Arg::with_name("max_overhang")
+            .long("max-overhang")
+            .help("Warn about overhangs reaching further than this many blocks from anything holding them up.")
+            .takes_value(true),
+        Arg::with_name("report")
+            .long("report")
+            .help("Write a JSON report of blocks that would fall, blocks that would pop off and overhangs that are too wide to this file.")
+            .takes_value(true),
+        Arg::with_name("materials")
+            .long("materials")
+            .help("Write how many of each block the output needs, in stacks and shulker boxes, to this file. Written as CSV or JSON for .csv or .json files, and as a text table otherwise.")
+            .takes_value(true),
+        Arg::with_name("materials_by_layer")
+            .long("materials-by-layer")
+            .help("Break the bill of materials down by Y layer.")
+            .requires("materials"),
+        Arg::with_name("guide")
+            .long("guide")
+            .help("Write a build guide to this directory: an image of each layer with coordinates and a legend, and an index.html page showing them in order.")
+            .takes_value(true),
+        Arg::with_name("preview")
+            .long("preview")
+            .help("Render a preview of the output to this PNG file, to check its orientation and scale.")
+            .takes_value(true),
+        Arg::with_name("preview_view")
+            .long("preview-view")
+            .help("Which way the preview looks at the output: iso (from above the south east corner), top, front (from the south) or side (from the east). Defaults to iso.")
+            .takes_value(true)
+            .possible_values(&["iso", "isometric", "top", "front", "side"])
+            .requires("preview"),
+        Arg::with_name("x_rot")
             .short("x")
             .help("Each -x specified rotates the model on the x axis by 90 degrees.")
             .multiple(true)
-            .takes_value(false)
-        )
-        .arg(Arg::with_name("y_rot")
+            .takes_value(false),
+        Arg::with_name("y_rot")
             .short("y")
             .help("Each -y specified rotates the model on the y axis by 90 degrees.")
             .multiple(true)
-            .takes_value(false)
-        )
-        .arg(Arg::with_name("z_rot")
+            .takes_value(false),
+        Arg::with_name("z_rot")
             .short("z")
             .help("Each -z specified rotates the model on the z axis by 90 degrees.")
             .multiple(true)
-            .takes_value(false)
-        )
-        .arg(Arg::with_name("format")
+            .takes_value(false),
+        Arg::with_name("rotate")
+            .long("rotate")
+            .help("Rotate the model by arbitrary Euler angles in degrees, given as X,Y,Z. Applied after any -x/-y/-z rotations.")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .conflicts_with("quaternion"),
+        Arg::with_name("quaternion")
+            .long("quaternion")
+            .help("Rotate the model by a quaternion, given as W,X,Y,Z. Applied after any -x/-y/-z rotations.")
+            .takes_value(true)
+            .allow_hyphen_values(true),
+        Arg::with_name("translate")
+            .long("translate")
+            .help("Offset the model by X,Y,Z model units after rotating. Useful to align the model within the voxel grid.")
+            .takes_value(true)
+            .allow_hyphen_values(true),
+        Arg::with_name("axis_scale")
+            .long("axis-scale")
+            .help("Scale the model by X,Y,Z along each axis before rotating, e.g. 1,0.5,1 to squash it vertically.")
+            .takes_value(true),
+        Arg::with_name("up_axis")
+            .long("up-axis")
+            .help("The axis that points up in the input model. Defaults to Z for STL, the file's own up axis for COLLADA, and Y otherwise.")
+            .takes_value(true)
+            .possible_values(&["x", "y", "z"]),
+        Arg::with_name("handedness")
+            .long("handedness")
+            .help("Whether the input model uses a left- or right-handed coordinate system. Set to left if the output comes out mirrored. Defaults to right.")
+            .takes_value(true)
+            .possible_values(&["left", "right"]),
+        Arg::with_name("format")
             .long("format")
             .short("f")
             .help("The file format in which to output the model. Valid options are: structure (str/nbt), schematic (sch/schem).")
             .takes_value(true)
-            .possible_values(&["structure", "str", "nbt", "schematic", "sch", "schem"])
-        )
-        .arg(Arg::with_name("threads")
+            .possible_values(&["structure", "str", "nbt", "schematic", "sch", "schem"]),
+        Arg::with_name("threads")
             .long("threads")
             .short("t")
             .help("Manually specify the number of threads to use. The default is [num physical threads] - 1.")
-            .takes_value(true)
-        )
-        .get_matches_from(env::args());
-
-    let path_prefix = matches
-        .value_of("output")
-        .map(|s| s.to_string())
-        .unwrap_or_default();
-    let path_prefix = Path::new(&path_prefix);
-
-    let config = AppConfig::from_argmatch(matches)?;
+            .takes_value(true),
+    ]
+}
 
+fn spawn_threads(config: &AppConfig) -> anyhow::Result<()> {
     log::info!("Spawning {} threads", config.threads);
     rayon::ThreadPoolBuilder::new()
         .num_threads(config.threads)
         .build_global()?;
+    Ok(())
+}
 
-    let input_path = config.input_path.clone();
-    let path = Path::new(&input_path).parent().unwrap();
-    let file_stem = config.filename.clone();
-    let file_ending = config.nbtify.file_ending();
-    let nbt = to_schematic(config)?;
-
-    // Output nbt to file.
-
-    std::fs::create_dir_all(path_prefix);
-    let output_path = path_prefix.join(&format!("{}.{}", file_stem, file_ending));
-    log::info!("Writing to '{}'", output_path.to_str().unwrap());
-
-    let mut file = File::create(output_path.clone())
-        .unwrap_or_else(|_| panic!("Could not create file '{:?}'", output_path));
+/// Print slices of the converted model, or of an existing schematic, and step through them with the keys typed
+/// in the terminal.
+fn view(matches: &ArgMatches) -> anyhow::Result<()> {
+    let input = matches.value_of("input").unwrap_or_default();
+    let extension = Path::new(input)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let (grid, default) = match extension.as_str() {
+        // Every voxel read from a schematic has its own block
+        "schem" | "nbt" => (read_schematic(input)?, BlockState::air()),
+        _ => {
+            let config = AppConfig::from_argmatch(matches.clone())?;
+            spawn_threads(&config)?;
+            (to_voxel_grid(&config)?, config.output_options().block)
+        }
+    };
+    let legend = Legend::new(&grid, &default);
+    let colour = !matches.is_present("no_colour")
+        && env::var_os("NO_COLOR").is_none()
+        && io::stdout().is_terminal();
+    let show = |axis: Axis, index: i32| -> anyhow::Result<()> {
+        print!(
+            "{}",
+            render_slice(&grid, &default, &legend, axis, index, colour)?
+        );
+        Ok(())
+    };
 
-    nbt.to_gzip_writer(&mut file)?;
+    let mut axis: Axis = matches.value_of("axis").unwrap_or("y").parse()?;
+    if matches.is_present("all") {
+        for index in 0..slice_count(&grid, axis) {
+            show(axis, index)?;
+            println!();
+        }
+        return Ok(());
+    }
+    let mut index = match matches.value_of("layer") {
+        Some(layer) => layer
+            .parse()
+            .with_context(|| format!("--layer should be a whole number, not {:?}", layer))?,
+        None => 0,
+    };
+    show(axis, index)?;
+    if matches.is_present("layer") || !io::stdin().is_terminal() {
+        return Ok(());
+    }
 
-    Ok(())
+    let mut line = String::new();
+    loop {
+        print!("Enter or n: next, p: previous, a number: go to it, x/y/z: change axis, q: quit > ");
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let last = slice_count(&grid, axis) - 1;
+        let (next_axis, next) = match line.trim() {
+            "" | "n" | "+" => (axis, (index + 1).min(last)),
+            "p" | "-" => (axis, (index - 1).max(0)),
+            "q" => return Ok(()),
+            command => match (command.parse::<i32>(), command.parse::<Axis>()) {
+                (Ok(number), _) => (axis, number),
+                (_, Ok(new_axis)) => (new_axis, index.clamp(0, slice_count(&grid, new_axis) - 1)),
+                _ => {
+                    println!("Unknown command {:?}", command);
+                    continue;
+                }
+            },
+        };
+        match show(next_axis, next) {
+            Ok(()) => {
+                axis = next_axis;
+                index = next;
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
 }
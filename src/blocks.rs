@@ -0,0 +1,420 @@
+use crate::error::{Error, Result};
+use crate::version::{MinecraftVersion, VersionTable};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// The block registry, compiled into the binary like the version table.
+const EMBEDDED_BLOCKS: &str = include_str!("../blocks.toml");
+
+/// The namespace of vanilla blocks, which is added to block IDs without one.
+pub const DEFAULT_NAMESPACE: &str = "minecraft";
+
+/// How many similar block IDs to suggest for an unknown block.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// How similar a block ID has to be to an unknown one to be suggested, from 0 to 1.
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// The layout of blocks.toml.
+#[derive(Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    properties: HashMap<String, BTreeMap<String, Vec<String>>>,
+    #[serde(default)]
+    blocks: HashMap<String, BlockEntry>,
+}
+
+#[derive(Deserialize)]
+struct BlockEntry {
+    since: Option<String>,
+    until: Option<String>,
+    #[serde(default)]
+    properties: Vec<String>,
+    support: Option<Support>,
+}
+
+/// What a block needs around it to stay where it's built.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Support {
+    /// Falls when there's nothing under it, like sand or anvils
+    Gravity,
+    /// Pops off without a block under it, like carpets or torches
+    Below,
+    /// Pops off without the block it's attached to, like buttons or lanterns.
+    /// Properties like `face`, `facing` and `hanging` say which block that is.
+    Attached,
+}
+
+/// What the registry knows about a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+    /// The DataVersion that added the block
+    pub since: i32,
+    /// The DataVersion that removed or renamed the block, if any
+    pub until: Option<i32>,
+    /// Each block state property, and the values it can take
+    pub properties: BTreeMap<String, Vec<String>>,
+    /// What the block needs around it to stay where it's built, if anything
+    pub support: Option<Support>,
+}
+
+impl BlockInfo {
+    pub fn exists_in(&self, version: &MinecraftVersion) -> bool {
+        let v = version.data_version();
+        v >= self.since && self.until.is_none_or(|until| v < until)
+    }
+}
+
+/// Add the `minecraft:` namespace to a block ID if it doesn't have one, e.g. "stone" -> "minecraft:stone".
+pub fn namespaced(block: &str) -> String {
+    let block = block.trim().to_lowercase();
+    if block.contains(':') {
+        block
+    } else {
+        format!("{}:{}", DEFAULT_NAMESPACE, block)
+    }
+}
+
+/// A block and its block state properties, e.g. `minecraft:oak_log[axis=y]`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockState {
+    /// The namespaced block ID
+    pub name: String,
+    /// Block state properties, sorted by name
+    pub properties: BTreeMap<String, String>,
+}
+
+impl BlockState {
+    /// A block without any properties. The name is namespaced if needed.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: namespaced(name),
+            properties: BTreeMap::new(),
+        }
+    }
+
+    pub fn air() -> Self {
+        Self::new("air")
+    }
+
+    /// The same block with a property set.
+    pub fn with(mut self, property: &str, value: &str) -> Self {
+        self.properties
+            .insert(property.to_string(), value.to_string());
+        self
+    }
+
+    pub fn get(&self, property: &str) -> Option<&str> {
+        self.properties.get(property).map(String::as_str)
+    }
+}
+
+impl FromStr for BlockState {
+    type Err = Error;
+
+    /// Parse a block state in the same syntax as Minecraft commands, e.g. `oak_log[axis=y]`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |message: &str| Error::InvalidBlockState {
+            block: s.trim().to_string(),
+            message: message.to_string(),
+        };
+        let s = s.trim();
+        let (name, properties) = match s.split_once('[') {
+            Some((name, rest)) => {
+                let properties = rest
+                    .strip_suffix(']')
+                    .ok_or_else(|| invalid("Missing a closing ']'"))?;
+                (name, Some(properties))
+            }
+            None => (s, None),
+        };
+        if name.trim().is_empty() || name.contains(|c: char| c.is_whitespace() || c == ']') {
+            return Err(invalid("Not a valid block ID"));
+        }
+
+        let mut state = BlockState::new(name);
+        for property in properties
+            .into_iter()
+            .flat_map(|p| p.split(','))
+            .filter(|p| !p.trim().is_empty())
+        {
+            let (key, value) = property
+                .split_once('=')
+                .ok_or_else(|| invalid("Properties should look like key=value"))?;
+            let (key, value) = (key.trim().to_lowercase(), value.trim().to_lowercase());
+            if key.is_empty() || value.is_empty() {
+                return Err(invalid("Properties should look like key=value"));
+            }
+            if state.properties.insert(key, value).is_some() {
+                return Err(invalid("A property is set more than once"));
+            }
+        }
+        Ok(state)
+    }
+}
+
+impl fmt::Display for BlockState {
+    /// Formats the block state the way Sponge schematic palettes key them, e.g. `minecraft:oak_log[axis=y]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.properties.is_empty() {
+            let properties: Vec<String> = self
+                .properties
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            write!(f, "[{}]", properties.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// The vanilla blocks of each version, used to catch typos in block IDs before they turn into air in game.
+#[derive(Clone, Debug)]
+pub struct BlockRegistry {
+    blocks: HashMap<String, BlockInfo>,
+    allow_unknown: bool,
+}
+
+impl BlockRegistry {
+    /// The registry that ships with threed2vox.
+    pub fn embedded() -> Self {
+        Self::embedded_file()
+            .and_then(|file| Self::from_file(file, "blocks.toml"))
+            .expect("The embedded blocks.toml is invalid")
+    }
+
+    /// The embedded registry, with blocks and property sets added or replaced by those in the TOML file at `path`.
+    pub fn with_overrides(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let overrides: RegistryFile =
+            toml::from_str(&content).map_err(|e| Error::parse(path, e))?;
+        let mut file = Self::embedded_file()?;
+        file.properties.extend(overrides.properties);
+        file.blocks.extend(overrides.blocks);
+        Self::from_file(file, path)
+    }
+
+    fn embedded_file() -> Result<RegistryFile> {
+        toml::from_str(EMBEDDED_BLOCKS).map_err(|e| Error::parse("blocks.toml", e))
+    }
+
+    fn from_file(file: RegistryFile, path: &str) -> Result<Self> {
+        let versions = VersionTable::embedded().strict(true);
+        let data_version = |name: &str| {
+            versions
+                .parse(name)
+                .map(|v| v.data_version())
+                .map_err(|e| Error::parse(path, e))
+        };
+
+        let mut blocks = HashMap::new();
+        for (name, entry) in file.blocks {
+            let mut properties = BTreeMap::new();
+            for set in &entry.properties {
+                let set = file.properties.get(set).ok_or_else(|| {
+                    Error::parse(path, format!("{} uses unknown properties {:?}", name, set))
+                })?;
+                properties.extend(set.clone());
+            }
+            let info = BlockInfo {
+                since: match &entry.since {
+                    Some(since) => data_version(since)?,
+                    None => crate::version::FLATTENING_DATA_VERSION,
+                },
+                until: entry.until.as_deref().map(data_version).transpose()?,
+                properties,
+                support: entry.support,
+            };
+            blocks.insert(namespaced(&name), info);
+        }
+
+        Ok(Self {
+            blocks,
+            allow_unknown: false,
+        })
+    }
+
+    /// Log unknown blocks as warnings instead of failing, e.g. for blocks newer than the registry.
+    pub fn allow_unknown(mut self, allow_unknown: bool) -> Self {
+        self.allow_unknown = allow_unknown;
+        self
+    }
+
+    /// Look up a block, with or without its namespace.
+    pub fn get(&self, block: &str) -> Option<&BlockInfo> {
+        self.blocks.get(&namespaced(block))
+    }
+
+    /// Parse a block state and check that the block and its properties exist in `version`.
+    /// Blocks from other namespaces, e.g. from mods, can't be checked and are always accepted.
+    pub fn validate(&self, block: &str, version: &MinecraftVersion) -> Result<BlockState> {
+        let state: BlockState = block.parse()?;
+        let (namespace, path) = state.name.split_once(':').unwrap();
+        if namespace != DEFAULT_NAMESPACE || !version.is_flattened() {
+            return Ok(state);
+        }
+
+        let error = match self.get(&state.name) {
+            Some(info) if info.exists_in(version) => match Self::check_properties(&state, info) {
+                Ok(()) => return Ok(state),
+                Err(message) => Error::InvalidBlockState {
+                    block: state.to_string(),
+                    message,
+                },
+            },
+            _ => Error::UnknownBlock {
+                block: state.name.clone(),
+                version: version.to_string(),
+                suggestions: self.suggestions(path, version),
+            },
+        };
+        if self.allow_unknown {
+            log::warn!("{}", error);
+            Ok(state)
+        } else {
+            Err(error)
+        }
+    }
+
+    fn check_properties(state: &BlockState, info: &BlockInfo) -> std::result::Result<(), String> {
+        for (key, value) in &state.properties {
+            let values = info.properties.get(key).ok_or_else(|| {
+                let known: Vec<&str> = info.properties.keys().map(String::as_str).collect();
+                match known.as_slice() {
+                    [] => format!("{} doesn't have any properties", state.name),
+                    known => format!(
+                        "{} doesn't have a property {:?}. It has: {}",
+                        state.name,
+                        key,
+                        known.join(", ")
+                    ),
+                }
+            })?;
+            if !values.contains(value) {
+                return Err(format!(
+                    "{:?} can't be {:?}. It can be: {}",
+                    key,
+                    value,
+                    values.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The blocks in `version` with IDs most like `path`, best first.
+    fn suggestions(&self, path: &str, version: &MinecraftVersion) -> Vec<String> {
+        let mut ranked: Vec<(f64, &String)> = self
+            .blocks
+            .iter()
+            .filter(|(_, info)| info.exists_in(version))
+            .map(|(id, _)| {
+                let other = id.split_once(':').map_or(id.as_str(), |(_, p)| p);
+                (strsim::normalized_damerau_levenshtein(path, other), id)
+            })
+            .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+            .collect();
+        ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then_with(|| a.1.cmp(b.1)));
+        ranked
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, id)| id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(name: &str) -> MinecraftVersion {
+        MinecraftVersion::parse(name).unwrap()
+    }
+
+    #[test]
+    fn adds_the_namespace() {
+        assert_eq!(namespaced("stone"), "minecraft:stone");
+        assert_eq!(namespaced(" Oak_Log "), "minecraft:oak_log");
+        assert_eq!(namespaced("create:shaft"), "create:shaft");
+    }
+
+    #[test]
+    fn validates_blocks_by_version() {
+        let registry = BlockRegistry::embedded();
+        assert_eq!(
+            registry.validate("stone", &version("1.16")).unwrap(),
+            BlockState::new("minecraft:stone")
+        );
+        assert!(registry.validate("deepslate", &version("1.16")).is_err());
+        assert!(registry.validate("deepslate", &version("1.17")).is_ok());
+        assert!(registry.validate("grass_path", &version("1.16")).is_ok());
+        assert!(registry.validate("grass_path", &version("1.17")).is_err());
+        assert!(registry.validate("create:shaft", &version("1.16")).is_ok());
+    }
+
+    #[test]
+    fn suggests_similar_blocks() {
+        match BlockRegistry::embedded().validate("stnoe", &version("1.16")) {
+            Err(Error::UnknownBlock { suggestions, .. }) => {
+                assert_eq!(suggestions[0], "minecraft:stone")
+            }
+            other => panic!("Expected an unknown block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn can_allow_unknown_blocks() {
+        let registry = BlockRegistry::embedded().allow_unknown(true);
+        assert_eq!(
+            registry.validate("stnoe", &version("1.16")).unwrap(),
+            BlockState::new("minecraft:stnoe")
+        );
+    }
+
+    #[test]
+    fn parses_block_states() {
+        let state: BlockState = "oak_log[axis=y]".parse().unwrap();
+        assert_eq!(state, BlockState::new("oak_log").with("axis", "y"));
+        let state: BlockState = " stone_brick_stairs[half=top, facing=north] "
+            .parse()
+            .unwrap();
+        assert_eq!(
+            state.to_string(),
+            "minecraft:stone_brick_stairs[facing=north,half=top]"
+        );
+        assert_eq!(BlockState::new("stone").to_string(), "minecraft:stone");
+        assert!("oak_log[axis=y".parse::<BlockState>().is_err());
+        assert!("oak_log[axis]".parse::<BlockState>().is_err());
+        assert!("oak_log[axis=y,axis=x]".parse::<BlockState>().is_err());
+        assert!("".parse::<BlockState>().is_err());
+    }
+
+    #[test]
+    fn validates_block_state_properties() {
+        let registry = BlockRegistry::embedded();
+        let v = version("1.16");
+        assert!(registry.validate("oak_log[axis=x]", &v).is_ok());
+        assert!(matches!(
+            registry.validate("oak_log[axis=w]", &v),
+            Err(Error::InvalidBlockState { .. })
+        ));
+        assert!(matches!(
+            registry.validate("stone[axis=x]", &v),
+            Err(Error::InvalidBlockState { .. })
+        ));
+    }
+
+    #[test]
+    fn knows_block_properties() {
+        let registry = BlockRegistry::embedded();
+        let log = registry.get("oak_log").unwrap();
+        assert_eq!(log.properties["axis"], vec!["x", "y", "z"]);
+        let stairs = registry.get("minecraft:stone_brick_stairs").unwrap();
+        assert!(stairs.properties.contains_key("waterlogged"));
+        assert!(stairs.properties.contains_key("shape"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Combines voxel grids with union, intersection, difference and XOR, e.g. carving a door out of a wall.
+//! Grids are aligned by their origins, so they must have been voxelised with the same voxel size and transform.
+
+use crate::error::{Error, Result};
+use crate::voxel_grid::VoxelGrid;
+use nalgebra::Vector3;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BooleanOp {
+    /// Voxels filled in either grid
+    Union,
+    /// Voxels filled in both grids
+    Intersection,
+    /// Voxels filled in the first grid but not the second
+    Difference,
+    /// Voxels filled in exactly one of the grids
+    Xor,
+}
+
+impl FromStr for BooleanOp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "union" | "add" => Ok(BooleanOp::Union),
+            "intersect" | "intersection" => Ok(BooleanOp::Intersection),
+            "subtract" | "difference" => Ok(BooleanOp::Difference),
+            "xor" => Ok(BooleanOp::Xor),
+            other => Err(Error::invalid_argument(
+                "combine",
+                format!("{:?} should be union, intersect, subtract or xor", other),
+            )),
+        }
+    }
+}
+
+/// How far `b`'s voxels are from the voxels of `a` with the same coordinates, in voxels.
+fn offset(a: &VoxelGrid, b: &VoxelGrid) -> Result<(i32, i32, i32)> {
+    let relative = (a.voxel_size - b.voxel_size).abs() / a.voxel_size;
+    if relative.is_nan() || relative >= 1e-4 {
+        return Err(Error::invalid_argument(
+            "combine",
+            format!(
+                "Grids with different voxel sizes ({} and {}) can't be combined",
+                a.voxel_size, b.voxel_size
+            ),
+        ));
+    }
+    let offset: Vector3<f32> = (b.origin - a.origin) / a.voxel_size;
+    let rounded = offset.map(f32::round);
+    if (offset - rounded).amax() > 1e-2 {
+        return Err(Error::invalid_argument(
+            "combine",
+            "The grids' voxels don't line up, so they can't be combined",
+        ));
+    }
+    Ok((rounded.x as i32, rounded.y as i32, rounded.z as i32))
+}
+
+/// Combine `b` into `a`, growing `a` if `b`'s voxels reach outside it. Voxels that `b` adds keep `b`'s blocks and
+/// surfaces, and `b`'s parts are added to `a`'s. Returns how many voxels of `a` changed.
+pub fn combine(a: &mut VoxelGrid, b: &VoxelGrid, op: BooleanOp) -> Result<usize> {
+    let (mut ox, mut oy, mut oz) = offset(a, b)?;
+
+    if matches!(op, BooleanOp::Union | BooleanOp::Xor) {
+        let moved: Vec<_> = b
+            .filled()
+            .map(|(x, y, z)| (x + ox, y + oy, z + oz))
+            .collect();
+        if let Some(&first) = moved.first() {
+            let (min, max) = moved.iter().fold((first, first), |(min, max), p| {
+                (
+                    (min.0.min(p.0), min.1.min(p.1), min.2.min(p.2)),
+                    (max.0.max(p.0), max.1.max(p.1), max.2.max(p.2)),
+                )
+            });
+            let shift = a.grow_to_fit(min, max);
+            ox += shift.0;
+            oy += shift.1;
+            oz += shift.2;
+        }
+    }
+
+    let add = |a: &mut VoxelGrid, (x, y, z): (i32, i32, i32)| {
+        let (ax, ay, az) = (x + ox, y + oy, z + oz);
+        a.set(ax, ay, az, true);
+        if let Some(block) = b.block(x, y, z) {
+            a.set_block(ax, ay, az, block.clone());
+        }
+        if let Some(surface) = b.surface(x, y, z) {
+            a.set_surface(ax, ay, az, *surface);
+        }
+    };
+    let changed: Vec<(i32, i32, i32)> = match op {
+        BooleanOp::Union | BooleanOp::Xor | BooleanOp::Difference => b
+            .filled()
+            .filter(|&(x, y, z)| match op {
+                BooleanOp::Union => !a.get(x + ox, y + oy, z + oz),
+                BooleanOp::Difference => *a.get(x + ox, y + oy, z + oz),
+                _ => true,
+            })
+            .collect(),
+        BooleanOp::Intersection => a
+            .filled()
+            .filter(|&(x, y, z)| !b.get(x - ox, y - oy, z - oz))
+            .map(|(x, y, z)| (x - ox, y - oy, z - oz))
+            .collect(),
+    };
+
+    for &(x, y, z) in &changed {
+        let (ax, ay, az) = (x + ox, y + oy, z + oz);
+        if *a.get(ax, ay, az) {
+            a.set(ax, ay, az, false);
+        } else {
+            add(a, (x, y, z));
+        }
+    }
+
+    // Keep track of b's parts in the voxels it shares with the result, after a's own parts
+    let first_part = a.parts().len();
+    let mut parts = a.parts().to_vec();
+    parts.extend_from_slice(b.parts());
+    a.set_parts(parts);
+    for (x, y, z) in b.filled() {
+        let (ax, ay, az) = (x + ox, y + oy, z + oz);
+        if b.parts_at(x, y, z).is_empty() || !a.get(ax, ay, az) {
+            continue;
+        }
+        let mut parts = a.parts_at(ax, ay, az).to_vec();
+        parts.extend(b.parts_at(x, y, z).iter().map(|i| i + first_part));
+        a.set_parts_at(ax, ay, az, parts);
+    }
+    Ok(changed.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::readers::reader::MeshPart;
+
+    /// A solid box of voxels from `min` to `max` inclusive, at a voxel size of 1.
+    fn cuboid(min: (i32, i32, i32), max: (i32, i32, i32)) -> VoxelGrid {
+        let dimensions = (max.0 - min.0 + 1, max.1 - min.1 + 1, max.2 - min.2 + 1);
+        let mut grid = VoxelGrid::from_fn(dimensions, |_, _, _| true);
+        grid.origin = Vector3::new(min.0 as f32, min.1 as f32, min.2 as f32);
+        grid
+    }
+
+    /// A 5 wide, 4 tall and 1 thick wall, and a 1 wide and 2 tall door through its middle.
+    fn wall_and_door() -> (VoxelGrid, VoxelGrid) {
+        (cuboid((0, 0, 0), (4, 3, 0)), cuboid((2, 0, -1), (2, 1, 1)))
+    }
+
+    #[test]
+    fn carves_out_a_door() {
+        let (mut wall, door) = wall_and_door();
+        assert_eq!(combine(&mut wall, &door, BooleanOp::Difference).unwrap(), 2);
+        assert!(!wall.get(2, 0, 0));
+        assert!(!wall.get(2, 1, 0));
+        assert!(*wall.get(2, 2, 0));
+        assert_eq!(wall.filled().count(), 18);
+    }
+
+    #[test]
+    fn intersects_grids() {
+        let (mut wall, door) = wall_and_door();
+        assert_eq!(
+            combine(&mut wall, &door, BooleanOp::Intersection).unwrap(),
+            18
+        );
+        assert_eq!(wall.filled().collect::<Vec<_>>().len(), 2);
+    }
+
+    #[test]
+    fn unites_grids_beyond_their_bounds() {
+        let (mut wall, door) = wall_and_door();
+        assert_eq!(combine(&mut wall, &door, BooleanOp::Union).unwrap(), 4);
+        // The wall grew towards negative Z to fit the door
+        assert_eq!(wall.dimensions, (5, 4, 3));
+        assert_eq!(wall.origin, Vector3::new(0.0, 0.0, -1.0));
+        assert!(*wall.get(2, 0, 0));
+        assert!(*wall.get(2, 0, 2));
+
+        let (mut wall, door) = wall_and_door();
+        assert_eq!(combine(&mut wall, &door, BooleanOp::Xor).unwrap(), 6);
+        assert_eq!(wall.filled().count(), 18 + 4);
+    }
+
+    #[test]
+    fn keeps_the_parts_of_both_grids() {
+        let part = |name: &str| MeshPart {
+            name: name.to_string(),
+            material: None,
+            triangles: 0..1,
+        };
+        let (mut wall, mut door) = wall_and_door();
+        wall.set_parts(vec![part("Wall")]);
+        for (x, y, z) in wall.filled().collect::<Vec<_>>() {
+            wall.set_parts_at(x, y, z, vec![0]);
+        }
+        door.set_parts(vec![part("Handle"), part("Door")]);
+        for (x, y, z) in door.filled().collect::<Vec<_>>() {
+            door.set_parts_at(x, y, z, vec![1]);
+        }
+        combine(&mut wall, &door, BooleanOp::Union).unwrap();
+        assert_eq!(wall.parts().len(), 3);
+        assert_eq!(wall.parts()[2].name, "Door");
+        // The door sticks out of the wall on both sides, and shares a voxel with it in the middle
+        assert_eq!(wall.parts_at(2, 0, 0), &[2]);
+        assert_eq!(wall.parts_at(2, 0, 1), &[0, 2]);
+        assert_eq!(wall.parts_at(0, 0, 1), &[0]);
+    }
+
+    #[test]
+    fn rejects_misaligned_grids() {
+        let (mut wall, mut door) = wall_and_door();
+        door.voxel_size = 0.5;
+        assert!(combine(&mut wall, &door, BooleanOp::Union).is_err());
+        door.voxel_size = 1.0;
+        door.origin.x += 0.5;
+        assert!(combine(&mut wall, &door, BooleanOp::Union).is_err());
+    }
+
+    #[test]
+    fn parses_operations() {
+        assert_eq!(
+            "Subtract".parse::<BooleanOp>().unwrap(),
+            BooleanOp::Difference
+        );
+        assert!("carve".parse::<BooleanOp>().is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Checks whether the finished grid stays standing when it's pasted or built: blocks that fall like sand,
+//! blocks that pop off like torches and buttons, and overhangs too wide to build out without scaffolding.
+
+use crate::blocks::{BlockRegistry, BlockState, Support};
+use crate::error::{Error, Result};
+use crate::voxel_grid::VoxelGrid;
+use crate::water::is_water;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// A block that won't stay where it's built.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Issue {
+    pub position: (i32, i32, i32),
+    pub block: String,
+}
+
+/// A connected run of voxels in one layer with nothing under them, reaching further than the allowed span from
+/// anything they could be built out from.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Overhang {
+    pub layer: i32,
+    pub voxels: usize,
+    /// The furthest any voxel is from a voxel resting on the layer below, or `None` if part of the overhang isn't
+    /// connected to one at all, e.g. because it hangs from above
+    pub span: Option<i32>,
+    pub min: (i32, i32, i32),
+    pub max: (i32, i32, i32),
+}
+
+/// Everything in a grid that would collapse or need scaffolding to build.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BuildReport {
+    /// Blocks like sand and anvils with nothing under them
+    pub falling: Vec<Issue>,
+    /// Blocks like torches, carpets and buttons without the block they're attached to
+    pub detached: Vec<Issue>,
+    /// Only filled in if a maximum span was given
+    pub overhangs: Vec<Overhang>,
+}
+
+impl BuildReport {
+    /// Check the grid. Voxels without a block use `default`. Overhangs are only looked for with a `max_span`.
+    pub fn check(
+        grid: &VoxelGrid,
+        default: &BlockState,
+        registry: &BlockRegistry,
+        max_span: Option<i32>,
+    ) -> Self {
+        let issue = |(x, y, z): (i32, i32, i32)| Issue {
+            position: (x, y, z),
+            block: grid.block(x, y, z).unwrap_or(default).to_string(),
+        };
+        Self {
+            falling: unsupported_gravity_blocks(grid, default, registry)
+                .into_iter()
+                .map(issue)
+                .collect(),
+            detached: detached_blocks(grid, default, registry)
+                .into_iter()
+                .map(issue)
+                .collect(),
+            overhangs: max_span
+                .map(|span| overhangs(grid, span))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.falling.is_empty() && self.detached.is_empty() && self.overhangs.is_empty()
+    }
+
+    /// Write the report as JSON.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| Error::io(path, e.into()))
+    }
+}
+
+/// The lowest filled layer, which rests on the ground it's built on.
+fn ground(grid: &VoxelGrid) -> i32 {
+    grid.filled().map(|(_, y, _)| y).min().unwrap_or_default()
+}
+
+fn support(registry: &BlockRegistry, block: &BlockState) -> Option<Support> {
+    registry.get(&block.name).and_then(|info| info.support)
+}
+
+/// The voxels with blocks that fall when built, because the voxel under them is empty, water or itself falls.
+/// Voxels without a block use `default`. Blocks in the lowest filled layer rest on the ground and always count as
+/// supported.
+pub fn unsupported_gravity_blocks(
+    grid: &VoxelGrid,
+    default: &BlockState,
+    registry: &BlockRegistry,
+) -> Vec<(i32, i32, i32)> {
+    let block_at = |x, y, z| grid.block(x, y, z).unwrap_or(default);
+    let mut candidates: Vec<_> = grid
+        .filled()
+        .filter(|&(x, y, z)| support(registry, block_at(x, y, z)) == Some(Support::Gravity))
+        .collect();
+    // Bottom up, so a column of sand over air falls as a whole
+    candidates.sort_unstable_by_key(|&(x, y, z)| (y, x, z));
+
+    let ground = ground(grid);
+    let mut unsupported = Vec::new();
+    let mut falling = HashSet::new();
+    for (x, y, z) in candidates {
+        if y == ground {
+            continue;
+        }
+        let supported = *grid.get(x, y - 1, z)
+            && !falling.contains(&(x, y - 1, z))
+            && !is_water(block_at(x, y - 1, z));
+        if !supported {
+            falling.insert((x, y, z));
+            unsupported.push((x, y, z));
+        }
+    }
+    unsupported
+}
+
+const FACES: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+/// The offset from a block to the block it's attached to, or `None` if it could be any of its neighbours.
+fn attached_to(block: &BlockState, support: Support) -> Option<(i32, i32, i32)> {
+    if support == Support::Below {
+        return Some((0, -1, 0));
+    }
+    let behind = || match block.get("facing")? {
+        "north" => Some((0, 0, 1)),
+        "south" => Some((0, 0, -1)),
+        "west" => Some((1, 0, 0)),
+        "east" => Some((-1, 0, 0)),
+        _ => None,
+    };
+    match (block.get("face"), block.get("hanging")) {
+        (Some("floor"), _) | (_, Some("false")) => Some((0, -1, 0)),
+        (Some("ceiling"), _) | (_, Some("true")) => Some((0, 1, 0)),
+        _ => behind(),
+    }
+}
+
+/// The voxels with blocks that pop off when built, because the block they're attached to is missing.
+/// Blocks in the lowest filled layer that need a block under them rest on the ground.
+pub fn detached_blocks(
+    grid: &VoxelGrid,
+    default: &BlockState,
+    registry: &BlockRegistry,
+) -> Vec<(i32, i32, i32)> {
+    let block_at = |x, y, z| grid.block(x, y, z).unwrap_or(default);
+    let ground = ground(grid);
+    // Blocks that need support can't support anything themselves
+    let holds = |x, y, z| {
+        grid.contains(x, y, z)
+            && *grid.get(x, y, z)
+            && !is_water(block_at(x, y, z))
+            && !matches!(
+                support(registry, block_at(x, y, z)),
+                Some(Support::Below) | Some(Support::Attached)
+            )
+    };
+    let mut detached: Vec<_> = grid
+        .filled()
+        .filter(|&(x, y, z)| {
+            let block = block_at(x, y, z);
+            let support = match support(registry, block) {
+                Some(support @ (Support::Below | Support::Attached)) => support,
+                _ => return false,
+            };
+            match attached_to(block, support) {
+                Some((0, -1, 0)) if y == ground => false,
+                Some((i, j, k)) => !holds(x + i, y + j, z + k),
+                None => !FACES.iter().any(|(i, j, k)| holds(x + i, y + j, z + k)),
+            }
+        })
+        .collect();
+    detached.sort_unstable_by_key(|&(x, y, z)| (y, x, z));
+    detached
+}
+
+/// The neighbours of a voxel within a layer, including diagonal ones, since thin surfaces are often only
+/// connected through their corners.
+const SIDES: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+/// The overhangs in the grid that reach further than `max_span` from the voxels holding them up, layer by layer,
+/// above the lowest filled layer.
+/// A voxel with nothing under it is one step from the layer below if it touches a voxel there diagonally, like the
+/// steps of a sloped roof, and otherwise one step further than its neighbours in the layer.
+pub fn overhangs(grid: &VoxelGrid, max_span: i32) -> Vec<Overhang> {
+    let mut layers: HashMap<i32, Vec<(i32, i32)>> = HashMap::new();
+    for (x, y, z) in grid.filled() {
+        layers.entry(y).or_default().push((x, z));
+    }
+    let ground = ground(grid);
+    let mut layers: Vec<_> = layers.into_iter().filter(|(y, _)| *y > ground).collect();
+    layers.sort_unstable_by_key(|(y, _)| *y);
+
+    let mut overhangs = Vec::new();
+    for (y, voxels) in layers {
+        let filled: HashSet<_> = voxels.iter().copied().collect();
+        let mut distances = HashMap::new();
+        let mut frontier = Vec::new();
+        for &(x, z) in &voxels {
+            let distance = if *grid.get(x, y - 1, z) {
+                0
+            } else if (-1..=1).any(|i| (-1..=1).any(|k| *grid.get(x + i, y - 1, z + k))) {
+                1
+            } else {
+                continue;
+            };
+            distances.insert((x, z), distance);
+            frontier.push((x, z));
+        }
+        // Breadth first from the resting voxels, then the leaning ones, so each voxel gets its shortest distance
+        frontier.sort_by_key(|p| distances[p]);
+        let mut i = 0;
+        while i < frontier.len() {
+            let (x, z) = frontier[i];
+            let distance = distances[&(x, z)] + 1;
+            for (dx, dz) in &SIDES {
+                let next = (x + dx, z + dz);
+                if filled.contains(&next) && !distances.contains_key(&next) {
+                    distances.insert(next, distance);
+                    frontier.push(next);
+                }
+            }
+            i += 1;
+        }
+
+        let too_far = |p: &(i32, i32)| distances.get(p).is_none_or(|&d| d > max_span);
+        let mut seen = HashSet::new();
+        let mut starts: Vec<_> = voxels.iter().filter(|p| too_far(p)).copied().collect();
+        starts.sort_unstable();
+        for start in starts {
+            if !seen.insert(start) {
+                continue;
+            }
+            let mut region = vec![start];
+            let mut j = 0;
+            while j < region.len() {
+                let (x, z) = region[j];
+                for (dx, dz) in &SIDES {
+                    let next = (x + dx, z + dz);
+                    if filled.contains(&next) && too_far(&next) && seen.insert(next) {
+                        region.push(next);
+                    }
+                }
+                j += 1;
+            }
+            let span = region
+                .iter()
+                .map(|p| distances.get(p).copied())
+                .collect::<Option<Vec<_>>>()
+                .and_then(|d| d.into_iter().max());
+            let (min, max) = region.iter().fold((start, start), |(min, max), &(x, z)| {
+                ((min.0.min(x), min.1.min(z)), (max.0.max(x), max.1.max(z)))
+            });
+            overhangs.push(Overhang {
+                layer: y,
+                voxels: region.len(),
+                span,
+                min: (min.0, y, min.1),
+                max: (max.0, y, max.1),
+            });
+        }
+    }
+    overhangs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_unsupported_gravity_blocks() {
+        let registry = BlockRegistry::embedded();
+        let sand = BlockState::new("sand");
+        let mut grid = VoxelGrid::new(3, 4, 1);
+        // A column of sand on the ground, two blocks of sand over air and sand over stone
+        grid.set(0, 0, 0, true);
+        grid.set(0, 1, 0, true);
+        grid.set(1, 2, 0, true);
+        grid.set(1, 3, 0, true);
+        grid.set_block(2, 0, 0, BlockState::new("stone"));
+        grid.set(2, 1, 0, true);
+        assert_eq!(
+            unsupported_gravity_blocks(&grid, &sand, &registry),
+            vec![(1, 2, 0), (1, 3, 0)]
+        );
+        assert!(unsupported_gravity_blocks(&grid, &BlockState::new("stone"), &registry).is_empty());
+    }
+
+    #[test]
+    fn finds_detached_blocks() {
+        let registry = BlockRegistry::embedded();
+        let stone = BlockState::new("stone");
+        let mut grid = VoxelGrid::new(3, 3, 3);
+        grid.set(1, 0, 1, true);
+        // A carpet on the stone and one in the air
+        grid.set_block(1, 1, 1, BlockState::new("white_carpet"));
+        grid.set_block(0, 2, 0, BlockState::new("white_carpet"));
+        // A button on the stone's north side, and one facing the wrong way
+        let button = BlockState::new("stone_button").with("face", "wall");
+        grid.set_block(1, 0, 0, button.clone().with("facing", "north"));
+        grid.set_block(0, 0, 1, button.with("facing", "east"));
+        // A hanging lantern under nothing
+        grid.set_block(2, 1, 2, BlockState::new("lantern").with("hanging", "true"));
+        assert_eq!(
+            detached_blocks(&grid, &stone, &registry),
+            vec![(0, 0, 1), (2, 1, 2), (0, 2, 0)]
+        );
+    }
+
+    #[test]
+    fn measures_overhangs() {
+        // A pillar holding up a 1 voxel thick plate that reaches 3 voxels to one side
+        let mut grid = VoxelGrid::new(5, 3, 1);
+        grid.set(0, 0, 0, true);
+        grid.set(0, 1, 0, true);
+        for x in 0..5 {
+            grid.set(x, 2, 0, true);
+        }
+        assert!(overhangs(&grid, 4).is_empty());
+        assert_eq!(
+            overhangs(&grid, 2),
+            vec![Overhang {
+                layer: 2,
+                voxels: 2,
+                span: Some(4),
+                min: (3, 2, 0),
+                max: (4, 2, 0),
+            }]
+        );
+
+        // The voxel next to the pillar leans on it, like a step of a sloped roof
+        grid.set(1, 1, 0, true);
+        assert_eq!(overhangs(&grid, 2)[0].voxels, 1);
+        assert_eq!(overhangs(&grid, 2)[0].span, Some(3));
+
+        // Nothing holds up a plate floating on its own
+        for x in 0..3 {
+            grid.set(x, 2, 0, false);
+        }
+        assert_eq!(overhangs(&grid, 2)[0].span, None);
+    }
+
+    #[test]
+    fn writes_json() {
+        let report = BuildReport {
+            falling: vec![Issue {
+                position: (1, 2, 3),
+                block: "minecraft:sand".to_string(),
+            }],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["falling"][0]["position"], serde_json::json!([1, 2, 3]));
+        assert!(json["overhangs"].as_array().unwrap().is_empty());
+    }
+}
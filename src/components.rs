@@ -0,0 +1,211 @@
+//! Finds the separate pieces of a voxelised model, e.g. to remove stray blocks left by photogrammetry
+//! or to warn about parts that would float in the air.
+
+use crate::error::{Error, Result};
+use crate::voxel_grid::VoxelGrid;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Which neighbouring voxels count as connected.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Connectivity {
+    /// Voxels sharing a face, i.e. 6 neighbours
+    Faces,
+    /// Voxels sharing a face, an edge or a corner, i.e. 26 neighbours
+    #[default]
+    Corners,
+}
+
+impl Connectivity {
+    fn offsets(&self) -> Vec<(i32, i32, i32)> {
+        let mut offsets = Vec::new();
+        for x in -1..=1i32 {
+            for y in -1..=1i32 {
+                for z in -1..=1i32 {
+                    let steps = x.abs() + y.abs() + z.abs();
+                    if steps == 1 || (steps > 1 && *self == Connectivity::Corners) {
+                        offsets.push((x, y, z));
+                    }
+                }
+            }
+        }
+        offsets
+    }
+}
+
+impl FromStr for Connectivity {
+    type Err = Error;
+
+    /// Parse the number of neighbours, 6 or 26.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "6" => Ok(Connectivity::Faces),
+            "26" => Ok(Connectivity::Corners),
+            other => Err(Error::invalid_argument(
+                "connectivity",
+                format!("{:?} should be 6 or 26", other),
+            )),
+        }
+    }
+}
+
+/// A set of filled voxels that are connected to each other, but not to any other filled voxel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Component {
+    /// The voxels in the component, in no particular order
+    pub voxels: Vec<(i32, i32, i32)>,
+}
+
+impl Component {
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+
+    /// The lowest and highest coordinates of the component's voxels.
+    pub fn bounds(&self) -> ((i32, i32, i32), (i32, i32, i32)) {
+        let start = self.voxels[0];
+        self.voxels.iter().fold((start, start), |(min, max), p| {
+            (
+                (min.0.min(p.0), min.1.min(p.1), min.2.min(p.2)),
+                (max.0.max(p.0), max.1.max(p.1), max.2.max(p.2)),
+            )
+        })
+    }
+}
+
+/// Split the filled voxels of the grid into connected components, largest first.
+pub fn components(grid: &VoxelGrid, connectivity: Connectivity) -> Vec<Component> {
+    let offsets = connectivity.offsets();
+    // Sorted so that the labelling, and the order of equally large components, is the same every time
+    let mut filled: Vec<_> = grid.filled().collect();
+    filled.sort_unstable();
+
+    let mut seen = HashSet::new();
+    let mut components = Vec::new();
+    for start in filled {
+        if !seen.insert(start) {
+            continue;
+        }
+        let mut voxels = vec![start];
+        let mut i = 0;
+        while i < voxels.len() {
+            let (x, y, z) = voxels[i];
+            for (ox, oy, oz) in &offsets {
+                let next = (x + ox, y + oy, z + oz);
+                if *grid.get(next.0, next.1, next.2) && seen.insert(next) {
+                    voxels.push(next);
+                }
+            }
+            i += 1;
+        }
+        components.push(Component { voxels });
+    }
+    components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+    components
+}
+
+fn remove(grid: &mut VoxelGrid, components: &[Component]) -> usize {
+    for component in components {
+        for &(x, y, z) in &component.voxels {
+            grid.set(x, y, z, false);
+        }
+    }
+    components.iter().map(Component::len).sum()
+}
+
+/// Empty every component with fewer than `min_size` voxels. Returns how many voxels were emptied.
+pub fn remove_small_components(
+    grid: &mut VoxelGrid,
+    connectivity: Connectivity,
+    min_size: usize,
+) -> usize {
+    let small: Vec<_> = components(grid, connectivity)
+        .into_iter()
+        .filter(|c| c.len() < min_size)
+        .collect();
+    remove(grid, &small)
+}
+
+/// Empty every component but the largest one. Returns how many voxels were emptied.
+pub fn keep_largest_component(grid: &mut VoxelGrid, connectivity: Connectivity) -> usize {
+    let components = components(grid, connectivity);
+    remove(grid, components.get(1..).unwrap_or_default())
+}
+
+/// The components that don't reach down to the lowest filled voxel of the grid, and would float in the air.
+pub fn floating_components(grid: &VoxelGrid, connectivity: Connectivity) -> Vec<Component> {
+    let components = components(grid, connectivity);
+    let ground = components
+        .iter()
+        .map(|c| c.bounds().0 .1)
+        .min()
+        .unwrap_or_default();
+    components
+        .into_iter()
+        .filter(|c| c.bounds().0 .1 > ground)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x3x3 block on the ground, a pair of voxels touching it diagonally and a lone voxel in the air.
+    fn grid() -> VoxelGrid {
+        let mut grid = VoxelGrid::from_fn((8, 8, 8), |x, y, z| x.max(y).max(z) < 3);
+        grid.set(3, 3, 3, true);
+        grid.set(4, 3, 3, true);
+        grid.set(7, 7, 7, true);
+        grid
+    }
+
+    #[test]
+    fn labels_components() {
+        let sizes = |connectivity| {
+            components(&grid(), connectivity)
+                .iter()
+                .map(Component::len)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(Connectivity::Faces), vec![27, 2, 1]);
+        assert_eq!(sizes(Connectivity::Corners), vec![29, 1]);
+        assert_eq!(
+            components(&grid(), Connectivity::Faces)[1].bounds(),
+            ((3, 3, 3), (4, 3, 3))
+        );
+    }
+
+    #[test]
+    fn removes_small_components() {
+        let mut small = grid();
+        assert_eq!(
+            remove_small_components(&mut small, Connectivity::Faces, 2),
+            1
+        );
+        assert!(!small.get(7, 7, 7));
+        assert!(*small.get(3, 3, 3));
+
+        let mut largest = grid();
+        assert_eq!(keep_largest_component(&mut largest, Connectivity::Faces), 3);
+        assert_eq!(largest.filled().count(), 27);
+    }
+
+    #[test]
+    fn finds_floating_components() {
+        let floating = floating_components(&grid(), Connectivity::Corners);
+        assert_eq!(floating.len(), 1);
+        assert_eq!(floating[0].voxels, vec![(7, 7, 7)]);
+        assert_eq!(floating_components(&grid(), Connectivity::Faces).len(), 2);
+    }
+
+    #[test]
+    fn parses_connectivity() {
+        assert_eq!("6".parse::<Connectivity>().unwrap(), Connectivity::Faces);
+        assert_eq!("26".parse::<Connectivity>().unwrap(), Connectivity::Corners);
+        assert!("18".parse::<Connectivity>().is_err());
+    }
+}
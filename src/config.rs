@@ -1,39 +1,115 @@
-use crate::nbtifier::{NBTIfy, SchematicV2, StructureFormat};
+use crate::blocks::{BlockRegistry, BlockState};
+use crate::boolean::BooleanOp;
+use crate::components::Connectivity;
+use crate::error::{Error, Result};
+use crate::height::HeightRule;
+use crate::hollow::Hollow;
+use crate::mix::BlockMix;
+use crate::morphology::MorphologyStep;
+use crate::nbtifier::{NBTIfy, OutputOptions, SchematicV2, StructureFormat};
+use crate::preview::View;
+use crate::progress::{CancellationToken, ProgressCallback};
 use crate::readers::obj::ObjReader;
 use crate::readers::reader::Reader;
 use crate::readers::{DaeReader, GltfReader, StlReader};
-use anyhow::{anyhow, Result};
+use crate::scene::Scene;
+use crate::transform::{Axis, Handedness, Transform};
+use crate::version::{MinecraftVersion, VersionTable};
+use crate::voxelizer::{VoxelOption, Voxelizer};
 use clap::ArgMatches;
-use std::collections::HashMap;
-use std::io::Read;
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
 use std::path::Path;
 
-pub enum VoxelOption {
-    /// Explicitly define a voxel size
-    VoxelSize(f32),
-    /// Define the voxel length of the longest axis of the model's AABB.
-    MeshSize(f32),
+/// Parse a length unit into how many metres it is, e.g. "cm" or "0.3048".
+pub fn parse_units(s: &str) -> Result<f32> {
+    match s.trim().to_lowercase().as_str() {
+        "mm" | "millimetre" | "millimeter" => Ok(0.001),
+        "cm" | "centimetre" | "centimeter" => Ok(0.01),
+        "m" | "metre" | "meter" => Ok(1.0),
+        "km" | "kilometre" | "kilometer" => Ok(1000.0),
+        "in" | "inch" => Ok(0.0254),
+        "ft" | "foot" | "feet" => Ok(0.3048),
+        "yd" | "yard" => Ok(0.9144),
+        other => other.parse().map_err(|_| {
+            Error::invalid_argument(
+                "units",
+                format!("{:?} is not a known unit or a number of metres", s),
+            )
+        }),
+    }
 }
 
 /// Pass around configuration options easily.
 pub struct Config {
     /// Determines the final size of the schematic
     pub voxel_size: VoxelOption,
-    /// Can be derived from a plaintext version, e.g. "Java 1.9.2", or can be supplied directly.
+    /// Can be derived from a plaintext version, e.g. "Java 1.9.2", or from a DataVersion.
     /// Used to output a schematic
-    pub data_version: i32,
-    /// The input file. Currently only supports .obj
+    pub version: MinecraftVersion,
+    /// The input file, either a model or a scene manifest
     pub input_path: String,
+    /// The models to voxelise, if the input is a scene manifest
+    pub scene: Option<Scene>,
     /// Just the filename portion of the input path
     pub filename: String,
-    /// The block to use for occupied voxels. Defaults to stone
-    pub block: String,
-    /// Rotations in radians
-    pub x_rot: f32,
-    pub y_rot: f32,
-    pub z_rot: f32,
+    /// The block state to use for occupied voxels. Defaults to minecraft:stone
+    pub block: BlockState,
+    /// A weighted mix of blocks to use instead of `block`, if more than one was given
+    pub block_mix: Option<BlockMix>,
+    /// Picks blocks by height, for voxels that don't set a block otherwise
+    pub height_rule: Option<HeightRule>,
+    /// The blocks of the target version, for validating and orienting blocks
+    pub blocks: BlockRegistry,
+    /// Whether to turn directional blocks like logs to follow the model's surface
+    pub orient: bool,
+    /// Whether to use stairs and slabs on sloped surfaces
+    pub smooth: bool,
+    /// Other models to voxelise in the same frame and combine with the input, in order
+    pub combine: Vec<(BooleanOp, String)>,
+    /// Dilations, erosions, openings and closings to apply to the grid, in order
+    pub morphology: Vec<MorphologyStep>,
+    /// Which neighbouring voxels count as connected when looking for separate pieces of the model
+    pub connectivity: Connectivity,
+    /// Remove pieces of the model with fewer voxels than this
+    pub min_component_size: Option<usize>,
+    /// Remove every piece of the model but the largest
+    pub keep_largest: bool,
+    /// Warn about pieces of the model that would float in the air
+    pub report_floating: bool,
+    /// Hollow out the model to save blocks, keeping its walls and pillars to hold it up
+    pub hollow: Option<Hollow>,
+    /// The names of parts or materials of the model to fill with water, e.g. "water"
+    pub water_parts: Vec<String>,
+    /// Fill empty voxels below this Y coordinate with water, where they're enclosed by the model
+    pub water_level: Option<i32>,
+    /// Report overhangs reaching further than this many blocks from what holds them up
+    pub max_overhang: Option<i32>,
+    /// Where to write the buildability report as JSON
+    pub report_path: Option<String>,
+    /// Where to write the bill of materials, as text, CSV or JSON depending on the extension
+    pub materials_path: Option<String>,
+    /// Whether to break the bill of materials down by Y layer
+    pub materials_by_layer: bool,
+    /// The directory to write a build guide to, with an image of each layer
+    pub guide_path: Option<String>,
+    /// Where to write a PNG preview of the output
+    pub preview_path: Option<String>,
+    /// Which way the preview looks at the output
+    pub preview_view: View,
+    /// Rotation, translation and scale applied to the model before voxelising
+    pub transform: Transform,
+    /// Overrides the up axis the reader assumes for the input file
+    pub up_axis: Option<Axis>,
+    /// Overrides the handedness the reader assumes for the input file
+    pub handedness: Option<Handedness>,
+    /// How many metres one model unit is. Overrides the unit recorded in the input file, if any.
+    pub metres_per_unit: Option<f32>,
     /// Number of threads to use
     pub threads: usize,
+    /// Called as the conversion progresses, e.g. to drive a progress bar
+    pub progress: Option<ProgressCallback>,
+    /// Lets the conversion be aborted from another thread
+    pub cancellation: Option<CancellationToken>,
     /// Output file format
     pub nbtify: Box<dyn NBTIfy>,
     /// Input file format
@@ -44,70 +120,247 @@ impl Config {
     pub fn from_argmatch(args: ArgMatches) -> Result<Self> {
         let input_path = args
             .value_of("input")
-            .ok_or_else(|| anyhow!("No input specified"))?
+            .ok_or_else(|| Error::invalid_argument("input", "No input specified"))?
             .to_string();
         let filename = Path::new(&input_path)
             .file_stem()
-            .unwrap_or_else(|| {
-                panic!(
-                    "The path '{}' doesn't seem to contain a file name",
-                    input_path
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| {
+                Error::invalid_argument(
+                    "input",
+                    format!(
+                        "The path '{}' doesn't seem to contain a file name",
+                        input_path
+                    ),
                 )
-            })
-            .to_str()
-            .unwrap()
+            })?
             .to_string();
 
         let file_extension = Path::new(&input_path)
             .extension()
             .unwrap_or_default()
-            .to_str()
-            .unwrap();
-
-        let reader: Box<dyn Reader> = match file_extension.to_lowercase().as_str() {
-            "obj" => Box::new(ObjReader),
-            "stl" => Box::new(StlReader),
-            "dae" => Box::new(DaeReader),
-            "gltf" | "glb" => Box::new(GltfReader),
-            f => panic!(
-                "The file extension {:?} is not supported. Valid files include: obj, stl",
-                f
-            ),
+            .to_string_lossy();
+        // A scene is read with the reader of its first model, for anything that asks for the input's format
+        let mut scene = None;
+        let reader = if file_extension.eq_ignore_ascii_case("toml") {
+            let loaded = Scene::load(&input_path)?;
+            let first = Path::new(&loaded.models[0].path)
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            scene = Some(loaded);
+            reader_for_extension(&first)?
+        } else {
+            reader_for_extension(&file_extension)?
         };
 
         let version = args
             .value_of("minecraft version")
-            .ok_or_else(|| anyhow!("No version specified"))?;
-        let block = args.value_of("block").unwrap_or("stone").to_string();
-        let data_version = match version.parse() {
-            Ok(n) => n,
-            Err(_) => Self::parse_version_string(version),
+            .ok_or_else(|| Error::invalid_argument("version", "No version specified"))?;
+        let versions = match args.value_of("versions_file") {
+            Some(path) => VersionTable::with_overrides(path)?,
+            None => VersionTable::embedded(),
+        }
+        .strict(args.is_present("strict_version"));
+        let version = versions.parse(version)?;
+        if !version.is_flattened() {
+            log::warn!(
+                "{} is older than 1.13, which threed2vox doesn't support. The output will likely not load.",
+                version
+            );
+        }
+
+        let blocks = match args.value_of("blocks_file") {
+            Some(path) => BlockRegistry::with_overrides(path)?,
+            None => BlockRegistry::embedded(),
+        }
+        .allow_unknown(args.is_present("allow_unknown_blocks"));
+        let seed = args
+            .value_of("seed")
+            .map(|s| {
+                s.parse()
+                    .map_err(|_| Error::invalid_argument("seed", "Should be a whole number"))
+            })
+            .transpose()?
+            .unwrap_or(0);
+        let patch_size = args
+            .value_of("patch_size")
+            .map(|s| {
+                s.parse()
+                    .map_err(|_| Error::invalid_argument("patch-size", "Should be a number"))
+            })
+            .transpose()?;
+        // Every mix is validated and shares the seed and patch size
+        let configure = |mix: BlockMix| {
+            let mix = mix
+                .try_map(|block| blocks.validate(&block.to_string(), &version))?
+                .seed(seed);
+            Ok(match patch_size {
+                Some(size) => mix.patch_size(size),
+                None => mix,
+            })
         };
 
-        // Checks for scale.
-        let voxel_size = match args.value_of("scale") {
-            Some(s) => {
-                let n = s.parse().unwrap_or(1.0);
-                VoxelOption::VoxelSize(n)
-            }
-            None => {
-                // If scale doesn't exist, check for size. If size doesn't exist, default is 1.0
-                let s = args.value_of("max_size").unwrap_or("1.0");
-                let n = s.parse().unwrap_or(1.0);
-                VoxelOption::MeshSize(n)
+        let block_mix = configure(args.value_of("block").unwrap_or("stone").parse()?)?;
+        let scene = scene.map(|scene| scene.try_map(configure)).transpose()?;
+        let block = block_mix.main_block().clone();
+        let block_mix = Some(block_mix).filter(|mix| !mix.is_single());
+
+        let height_rule = match (args.value_of("bands"), args.value_of("gradient")) {
+            (Some(s), _) => Some(HeightRule::bands(s)?),
+            (None, Some(s)) => Some(HeightRule::gradient(s)?.seed(seed)),
+            (None, None) => None,
+        }
+        .map(|rule| rule.try_map(configure))
+        .transpose()?;
+
+        // Checks for scale. The sizing options override each other, so at most one is present.
+        let voxel_size = if let Some(s) = args.value_of("scale") {
+            VoxelOption::VoxelSize(parse_floats(s, 1, "scale")?[0])
+        } else if let Some(s) = args.value_of("width") {
+            VoxelOption::AxisSize(Axis::X, parse_floats(s, 1, "width")?[0])
+        } else if let Some(s) = args.value_of("height") {
+            VoxelOption::AxisSize(Axis::Y, parse_floats(s, 1, "height")?[0])
+        } else if let Some(s) = args.value_of("length") {
+            VoxelOption::AxisSize(Axis::Z, parse_floats(s, 1, "length")?[0])
+        } else if let Some(s) = args.value_of("fit") {
+            let v = parse_floats(s, 3, "fit")?;
+            VoxelOption::FitBox(Vector3::new(v[0], v[1], v[2]))
+        } else if let Some(s) = args.value_of("real_scale") {
+            VoxelOption::RealScale(parse_floats(s, 1, "real-scale")?[0])
+        } else {
+            // If scale doesn't exist, check for size. If size doesn't exist, default is 1.0
+            let s = args.value_of("max_size").unwrap_or("1.0");
+            VoxelOption::MeshSize(parse_floats(s, 1, "size")?[0])
+        };
+        let metres_per_unit = args.value_of("units").map(parse_units).transpose()?;
+
+        // Rotations. The quarter turns from -x/-y/-z are applied before any arbitrary rotation.
+        let quarter_turns = Transform::quarter_turns(
+            args.occurrences_of("x_rot"),
+            args.occurrences_of("y_rot"),
+            args.occurrences_of("z_rot"),
+        );
+
+        let rotation = if let Some(s) = args.value_of("rotate") {
+            let v = parse_floats(s, 3, "rotate")?;
+            Transform::rotation_from_degrees(v[0], v[1], v[2])
+        } else if let Some(s) = args.value_of("quaternion") {
+            let v = parse_floats(s, 4, "quaternion")?;
+            UnitQuaternion::from_quaternion(Quaternion::new(v[0], v[1], v[2], v[3]))
+        } else {
+            UnitQuaternion::identity()
+        };
+
+        let mut transform = Transform {
+            rotation: rotation * quarter_turns,
+            ..Default::default()
+        };
+        if let Some(s) = args.value_of("translate") {
+            let v = parse_floats(s, 3, "translate")?;
+            transform.translation = Vector3::new(v[0], v[1], v[2]);
+        }
+        if let Some(s) = args.value_of("axis_scale") {
+            let v = parse_floats(s, 3, "axis-scale")?;
+            transform.scale = Vector3::new(v[0], v[1], v[2]);
+        }
+
+        let combine = args
+            .values_of("combine")
+            .map(|values| {
+                values
+                    .map(|value| {
+                        let (op, path) = value.split_once(':').ok_or_else(|| {
+                            Error::invalid_argument(
+                                "combine",
+                                format!("{:?} should look like OPERATION:FILE", value),
+                            )
+                        })?;
+                        Ok((op.parse()?, path.to_string()))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let morphology = args
+            .values_of("morph")
+            .map(|steps| steps.map(str::parse).collect::<Result<Vec<_>>>())
+            .transpose()?
+            .unwrap_or_default();
+
+        let connectivity = args
+            .value_of("connectivity")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+        let min_component_size = args
+            .value_of("min_component_size")
+            .map(|s| {
+                s.parse().map_err(|_| {
+                    Error::invalid_argument("min-component-size", "Should be a number of voxels")
+                })
+            })
+            .transpose()?;
+        let hollow = match args.value_of("hollow") {
+            Some(thickness) => {
+                let thickness = thickness
+                    .parse()
+                    .ok()
+                    .filter(|&t: &i32| t >= 1)
+                    .ok_or_else(|| {
+                        Error::invalid_argument(
+                            "hollow",
+                            "Should be a wall thickness of at least 1",
+                        )
+                    })?;
+                let spacing = args
+                    .value_of("support_spacing")
+                    .unwrap_or("8")
+                    .parse()
+                    .ok()
+                    .filter(|&s: &i32| s >= 0)
+                    .ok_or_else(|| {
+                        Error::invalid_argument("support-spacing", "Should be a number of blocks")
+                    })?;
+                Some(Hollow::new(thickness).support_spacing(Some(spacing).filter(|&s| s > 0)))
             }
+            None => None,
         };
 
-        // Rotations
-        let x_rot = std::f32::consts::FRAC_PI_2 * (args.occurrences_of("x_rot") as f32);
-        let y_rot = std::f32::consts::FRAC_PI_2 * (args.occurrences_of("y_rot") as f32);
-        let z_rot = std::f32::consts::FRAC_PI_2 * (args.occurrences_of("z_rot") as f32);
+        let water_parts = args
+            .values_of("water")
+            .map(|names| names.map(str::to_string).collect())
+            .unwrap_or_default();
+        let water_level = args
+            .value_of("water_level")
+            .map(|s| {
+                s.parse().map_err(|_| {
+                    Error::invalid_argument("water-level", "Should be an integer Y coordinate")
+                })
+            })
+            .transpose()?;
+        let max_overhang = args
+            .value_of("max_overhang")
+            .map(|s| {
+                s.parse()
+                    .ok()
+                    .filter(|&span: &i32| span >= 0)
+                    .ok_or_else(|| {
+                        Error::invalid_argument("max-overhang", "Should be a number of blocks")
+                    })
+            })
+            .transpose()?;
+
+        let up_axis = args.value_of("up_axis").map(str::parse).transpose()?;
+        let handedness = args.value_of("handedness").map(str::parse).transpose()?;
 
         let mut threads: usize = args
             .value_of("threads")
             .unwrap_or("0")
             .parse()
-            .expect("Arg 'threads' should be an integer.");
+            .map_err(|_| Error::invalid_argument("threads", "Should be an integer"))?;
 
         if threads < 1 {
             threads = num_cpus::get() - 1;
@@ -116,77 +369,173 @@ impl Config {
         let nbtify: Box<dyn NBTIfy> = match args.value_of("format").unwrap_or("schematic") {
             "schematic" | "schem" | "sch" => Box::new(SchematicV2),
             "structure" | "str" | "nbt" => Box::new(StructureFormat),
-            s => panic!(
-                "Somehow encountered string {:?} when it should've been impossible",
-                s
-            ),
+            s => {
+                return Err(Error::invalid_argument(
+                    "format",
+                    format!("{:?} is not a known format", s),
+                ))
+            }
         };
 
         Ok(Self {
             voxel_size,
-            data_version,
+            version,
             input_path,
+            scene,
             block,
+            block_mix,
+            height_rule,
+            blocks,
+            orient: !args.is_present("no_orient"),
+            smooth: args.is_present("smooth"),
+            combine,
+            morphology,
+            connectivity,
+            min_component_size,
+            keep_largest: args.is_present("keep_largest"),
+            report_floating: args.is_present("report_floating"),
+            hollow,
+            water_parts,
+            water_level,
+            max_overhang,
+            report_path: args.value_of("report").map(str::to_string),
+            materials_path: args.value_of("materials").map(str::to_string),
+            materials_by_layer: args.is_present("materials_by_layer"),
+            guide_path: args.value_of("guide").map(str::to_string),
+            preview_path: args.value_of("preview").map(str::to_string),
+            preview_view: args
+                .value_of("preview_view")
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or(View::Isometric),
             filename,
-            x_rot,
-            y_rot,
-            z_rot,
+            transform,
+            up_axis,
+            handedness,
+            metres_per_unit,
             threads,
+            progress: None,
+            cancellation: None,
             nbtify,
             reader,
         })
     }
 
-    pub fn parse_version_string(version: &str) -> i32 {
-        use strsim::normalized_levenshtein;
-
-        let mut toml_content = String::new();
-        let mut file = std::fs::File::open("minecraft_versions.toml")
-            .expect("Could not find minecraft_versions.toml");
-        file.read_to_string(&mut toml_content).unwrap();
-        let index: HashMap<String, i32> = toml::from_str(&toml_content).unwrap();
+    /// Build a voxelizer for the input file, using the reader's coordinate system and units unless overridden.
+    pub fn voxelizer(&self) -> Result<Voxelizer> {
+        self.voxelizer_for(self.reader.as_ref(), &self.input_path)
+    }
 
-        // First, try to look it up directly, and if it's in there return it
-        if index.contains_key(version) {
-            return index[version];
+    /// Build a voxelizer for any file with the same options as the input file, e.g. for a model to combine with it.
+    pub fn voxelizer_for(&self, reader: &dyn Reader, path: &str) -> Result<Voxelizer> {
+        let mut coordinate_system = reader.coordinate_system(path)?;
+        if let Some(up) = self.up_axis {
+            coordinate_system.up = up;
+        }
+        if let Some(handedness) = self.handedness {
+            coordinate_system.handedness = handedness;
         }
 
-        // Next, try to find the version str with the closest distance.
-        let result = index
-            .into_iter()
-            .map(|(k, v)| (normalized_levenshtein(&k, version), k, v))
-            // Find the max.
-            .fold((-1.0, String::new(), 0), |prev, this| {
-                if this.0 > prev.0 {
-                    this
-                } else {
-                    prev
+        let mut voxelizer = Voxelizer::new()
+            .voxel_size(self.voxel_size)
+            .transform(self.transform.clone())
+            .coordinate_system(coordinate_system);
+
+        // Only look up the file's units if they're needed
+        let metres_per_unit = match (self.metres_per_unit, self.voxel_size) {
+            (Some(m), _) => Some(m),
+            (None, VoxelOption::RealScale(_)) => {
+                let m = reader.metres_per_unit(path)?;
+                if m.is_none() {
+                    log::warn!(
+                        "The input file doesn't record its units. Use --units to specify them."
+                    );
                 }
-            });
-        println!(
-            "[INFO] Could not find version '{}', using closest match '{}'.",
-            version, result.1
-        );
-        result.2
+                m
+            }
+            _ => None,
+        };
+        if let Some(m) = metres_per_unit {
+            voxelizer = voxelizer.metres_per_unit(m);
+        }
+
+        Ok(self.report_to(voxelizer))
+    }
+
+    /// Give the voxelizer the config's progress callback and cancellation token.
+    pub fn report_to(&self, mut voxelizer: Voxelizer) -> Voxelizer {
+        if let Some(callback) = &self.progress {
+            let callback = callback.clone();
+            voxelizer = voxelizer.on_progress(move |p| callback(p));
+        }
+        if let Some(token) = &self.cancellation {
+            voxelizer = voxelizer.cancellation_token(token.clone());
+        }
+        voxelizer
+    }
+
+    /// The options needed to write the voxel grid to a file.
+    pub fn output_options(&self) -> OutputOptions {
+        OutputOptions::new(self.version.clone(), &self.filename, self.block.clone())
     }
 }
 
+/// Pick the reader for a file extension, e.g. "obj".
+pub fn reader_for_extension(extension: &str) -> Result<Box<dyn Reader>> {
+    match extension.to_lowercase().as_str() {
+        "obj" => Ok(Box::new(ObjReader)),
+        "stl" => Ok(Box::new(StlReader)),
+        "dae" => Ok(Box::new(DaeReader)),
+        "gltf" | "glb" => Ok(Box::new(GltfReader)),
+        f => Err(Error::UnsupportedFormat(f.to_string())),
+    }
+}
+
+/// Parse a comma-separated list of exactly `n` floats, e.g. "0,90,0".
+fn parse_floats(s: &str, n: usize, arg: &str) -> Result<Vec<f32>> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| {
+            Error::invalid_argument(arg, format!("Should be a list of numbers, got {:?}", s))
+        })?;
+    if values.len() != n {
+        return Err(Error::invalid_argument(
+            arg,
+            format!("Should have {} comma-separated values, got {:?}", n, s),
+        ));
+    }
+    Ok(values)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn it_works() {
-        assert_eq!(Config::parse_version_string("20w20a"), 2536)
+    fn rejects_unknown_extensions() {
+        assert!(matches!(
+            reader_for_extension("fbx"),
+            Err(Error::UnsupportedFormat(_))
+        ));
+        assert!(reader_for_extension("GLB").is_ok());
     }
 
     #[test]
-    fn prefers_full_match() {
-        assert_eq!(Config::parse_version_string("1.16"), 2566)
+    fn parses_units() {
+        assert_eq!(parse_units("ft").unwrap(), 0.3048);
+        assert_eq!(parse_units("0.5").unwrap(), 0.5);
+        assert!(parse_units("furlong").is_err());
     }
 
     #[test]
-    fn does_partial_match() {
-        assert_eq!(Config::parse_version_string("1.13-"), 1519)
+    fn parses_float_lists() {
+        assert_eq!(
+            parse_floats("1, -2.5,3", 3, "test").unwrap(),
+            vec![1.0, -2.5, 3.0]
+        );
+        assert!(parse_floats("1,2", 3, "test").is_err());
+        assert!(parse_floats("1,a,3", 3, "test").is_err());
     }
 }
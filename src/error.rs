@@ -0,0 +1,89 @@
+use thiserror::Error;
+
+/// Everything that can go wrong while converting a model.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The input file's extension doesn't match any reader
+    #[error(
+        "The file extension {0:?} is not supported. Valid files include: obj, stl, dae, gltf, glb"
+    )]
+    UnsupportedFormat(String),
+    /// The input file couldn't be parsed, or uses a feature the reader doesn't support
+    #[error("Could not parse '{path}': {message}")]
+    Parse { path: String, message: String },
+    /// The model doesn't contain any triangles to voxelise
+    #[error("The model '{0}' doesn't contain any triangles")]
+    EmptyMesh(String),
+    /// The voxel grid would exceed the maximum size along some axis
+    #[error("The voxel grid would be {x}x{y}x{z}, but at most {max} blocks are allowed per axis")]
+    GridTooLarge { x: i32, y: i32, z: i32, max: i32 },
+    /// The Minecraft version couldn't be resolved to a DataVersion
+    #[error("Unknown Minecraft version {version:?}{}", did_you_mean(.candidates))]
+    UnknownVersion {
+        version: String,
+        candidates: Vec<String>,
+    },
+    /// The Minecraft version isn't an exact match, and guessing was disabled or several versions matched equally well
+    #[error("{version:?} doesn't name a single Minecraft version{}", did_you_mean(.candidates))]
+    AmbiguousVersion {
+        version: String,
+        candidates: Vec<String>,
+    },
+    /// The block ID isn't in the block registry for the target version
+    #[error("The block {block:?} doesn't exist in Minecraft {version}{}", did_you_mean(.suggestions))]
+    UnknownBlock {
+        block: String,
+        version: String,
+        suggestions: Vec<String>,
+    },
+    /// The block state couldn't be parsed, or has properties the block doesn't have
+    #[error("Invalid block state {block:?}: {message}")]
+    InvalidBlockState { block: String, message: String },
+    /// An option had an invalid value
+    #[error("Invalid value for '{arg}': {message}")]
+    InvalidArgument { arg: String, message: String },
+    /// The conversion was aborted through its cancellation token
+    #[error("The conversion was cancelled")]
+    Cancelled,
+    #[error("Could not read '{path}': {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Nbt(#[from] nbt::Error),
+}
+
+impl Error {
+    pub(crate) fn parse(path: &str, message: impl ToString) -> Self {
+        Error::Parse {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn invalid_argument(arg: &str, message: impl ToString) -> Self {
+        Error::InvalidArgument {
+            arg: arg.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn io(path: &str, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+/// A suffix listing close matches, if there are any.
+fn did_you_mean(candidates: &[String]) -> String {
+    if candidates.is_empty() {
+        String::new()
+    } else {
+        format!(". Did you mean one of: {}?", candidates.join(", "))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
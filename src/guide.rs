@@ -0,0 +1,377 @@
+//! Writes a build guide: an image of each layer seen from above, with grid coordinates and a legend of the blocks
+//! in it, and an `index.html` page showing them in order, for building by hand without a schematic mod.
+
+use crate::blocks::{BlockState, DEFAULT_NAMESPACE};
+use crate::error::{Error, Result};
+use crate::nbtifier::{block_at, OutputOptions};
+use crate::raster::{Image, Rgb, BLACK, WHITE};
+use crate::voxel_grid::VoxelGrid;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The size of one block in the layer images, in pixels.
+const CELL: u32 = 16;
+/// How much the font is scaled up.
+const SCALE: u32 = 2;
+/// Every how many blocks the coordinates are labelled and the grid lines are darker.
+const MAJOR: i32 = 5;
+const MARGIN: u32 = 8;
+
+const GRID_LINE: Rgb = [210, 210, 210];
+const MAJOR_GRID_LINE: Rgb = [120, 120, 120];
+
+/// Colours that are easy to tell apart, for the most used blocks.
+const DISTINCT: [Rgb; 20] = [
+    [0x22, 0x22, 0x22],
+    [0xF3, 0xC3, 0x00],
+    [0x87, 0x56, 0x92],
+    [0xF3, 0x84, 0x00],
+    [0xA1, 0xCA, 0xF1],
+    [0xBE, 0x00, 0x32],
+    [0xC2, 0xB2, 0x80],
+    [0x84, 0x84, 0x82],
+    [0x00, 0x88, 0x56],
+    [0xE6, 0x8F, 0xAC],
+    [0x00, 0x67, 0xA5],
+    [0xF9, 0x93, 0x79],
+    [0x60, 0x4E, 0x97],
+    [0xF6, 0xA6, 0x00],
+    [0xB3, 0x44, 0x6C],
+    [0xDC, 0xD3, 0x00],
+    [0x88, 0x2D, 0x17],
+    [0x8D, 0xB6, 0x00],
+    [0x65, 0x45, 0x22],
+    [0x2B, 0x3D, 0x26],
+];
+
+/// The colour of the `index`th block of the legend. Past the distinct colours, hues are spread by the golden angle.
+fn colour(index: usize) -> Rgb {
+    if let Some(&colour) = DISTINCT.get(index) {
+        return colour;
+    }
+    let hue = (index as f64 * 137.508) % 360.0;
+    let (s, v) = (0.65, 0.8);
+    let c = v * s;
+    let x = c * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match (hue / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    [r, g, b].map(|channel| ((channel + m) * 255.0).round() as u8)
+}
+
+/// The block state as builders read it, without the vanilla namespace.
+pub(crate) fn label(block: &BlockState) -> String {
+    let label = block.to_string();
+    let prefix = format!("{}:", DEFAULT_NAMESPACE);
+    label
+        .strip_prefix(&prefix)
+        .map(str::to_string)
+        .unwrap_or(label)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A layer of the grid, as rows of blocks along Z, each a row of blocks along X.
+struct Layer<'a> {
+    y: i32,
+    rows: Vec<Vec<Option<&'a BlockState>>>,
+}
+
+impl<'a> Layer<'a> {
+    fn counts(&self) -> HashMap<&'a BlockState, usize> {
+        let mut counts = HashMap::new();
+        for block in self.rows.iter().flatten().flatten() {
+            *counts.entry(*block).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// The blocks in the grid, most used first, which is the order of the legend and their colours.
+fn legend<'a>(layers: &[Layer<'a>]) -> Vec<(&'a BlockState, usize)> {
+    let mut counts: HashMap<&BlockState, usize> = HashMap::new();
+    for layer in layers {
+        for (block, count) in layer.counts() {
+            *counts.entry(block).or_default() += count;
+        }
+    }
+    most_used_first(counts)
+}
+
+fn most_used_first(counts: HashMap<&BlockState, usize>) -> Vec<(&BlockState, usize)> {
+    let mut blocks: Vec<_> = counts.into_iter().collect();
+    blocks.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+    });
+    blocks
+}
+
+fn draw_layer(layer: &Layer, colours: &HashMap<&BlockState, Rgb>) -> Image {
+    let (width, length) = (layer.rows[0].len() as u32, layer.rows.len() as u32);
+    let line = Image::text_height(SCALE) + MARGIN;
+    let label_width = Image::text_width(&(length as i32 - 1).to_string(), SCALE);
+    let left = MARGIN + label_width + MARGIN;
+    let top = MARGIN + 2 * line;
+
+    let legend: Vec<_> = most_used_first(layer.counts())
+        .into_iter()
+        .map(|(block, count)| (colours[block], format!("{} x{}", label(block), count)))
+        .collect();
+    let legend_left = left + width * CELL + 2 * MARGIN;
+    let legend_width = legend
+        .iter()
+        .map(|(_, text)| CELL + MARGIN + Image::text_width(text, SCALE))
+        .max()
+        .unwrap_or_default();
+    let row = CELL + MARGIN / 2;
+
+    let mut image = Image::new(
+        legend_left + legend_width + MARGIN,
+        (top + length * CELL).max(top + legend.len() as u32 * row) + MARGIN,
+        WHITE,
+    );
+    image.draw_text(
+        MARGIN as i64,
+        MARGIN as i64,
+        &format!("Layer {} - north is up", layer.y),
+        SCALE,
+        BLACK,
+    );
+
+    for (z, blocks) in layer.rows.iter().enumerate() {
+        for (x, block) in blocks.iter().enumerate() {
+            if let Some(block) = block {
+                image.fill_rect(
+                    (left + x as u32 * CELL) as i64,
+                    (top + z as u32 * CELL) as i64,
+                    CELL,
+                    CELL,
+                    colours[block],
+                );
+            }
+        }
+    }
+    // Grid lines, drawn over the edges of the cells, and coordinates every few blocks
+    for x in 0..=width as i32 {
+        let colour = if x % MAJOR == 0 {
+            MAJOR_GRID_LINE
+        } else {
+            GRID_LINE
+        };
+        image.fill_rect(
+            (left + x as u32 * CELL) as i64,
+            top as i64,
+            1,
+            length * CELL + 1,
+            colour,
+        );
+        if x % MAJOR == 0 && x < width as i32 {
+            let y = (top - line) as i64;
+            image.draw_text(
+                (left + x as u32 * CELL) as i64 + 2,
+                y,
+                &x.to_string(),
+                SCALE,
+                BLACK,
+            );
+        }
+    }
+    for z in 0..=length as i32 {
+        let colour = if z % MAJOR == 0 {
+            MAJOR_GRID_LINE
+        } else {
+            GRID_LINE
+        };
+        image.fill_rect(
+            left as i64,
+            (top + z as u32 * CELL) as i64,
+            width * CELL + 1,
+            1,
+            colour,
+        );
+        if z % MAJOR == 0 && z < length as i32 {
+            let text = z.to_string();
+            let x = left - MARGIN - Image::text_width(&text, SCALE);
+            let y = top + z as u32 * CELL + (CELL - Image::text_height(SCALE)) / 2;
+            image.draw_text(x as i64, y as i64, &text, SCALE, BLACK);
+        }
+    }
+
+    for (i, (colour, text)) in legend.iter().enumerate() {
+        let y = top + i as u32 * row;
+        image.fill_rect(legend_left as i64, y as i64, CELL, CELL, *colour);
+        let text_y = y + (CELL - Image::text_height(SCALE)) / 2;
+        image.draw_text(
+            (legend_left + CELL + MARGIN) as i64,
+            text_y as i64,
+            text,
+            SCALE,
+            BLACK,
+        );
+    }
+    image
+}
+
+/// Write an image of each layer with blocks in it to `dir`, named by its Y coordinate, and an `index.html` page
+/// with the legend for the whole build and every layer in order. Returns how many layers were written.
+pub fn write_guide(grid: &VoxelGrid, options: &OutputOptions, dir: &str) -> Result<usize> {
+    std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    let (dx, dy, dz) = grid.dimensions;
+    // The same Y, Z, X order as the schematic's block data
+    let layers: Vec<_> = (0..dy)
+        .map(|y| Layer {
+            y,
+            rows: (0..dz)
+                .map(|z| (0..dx).map(|x| block_at(grid, options, x, y, z)).collect())
+                .collect(),
+        })
+        .filter(|layer| layer.rows.iter().flatten().any(Option::is_some))
+        .collect();
+
+    let legend = legend(&layers);
+    let colours: HashMap<_, _> = legend
+        .iter()
+        .enumerate()
+        .map(|(i, (block, _))| (*block, colour(i)))
+        .collect();
+
+    let mut html = String::new();
+    let title = escape(&options.name);
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    )
+    .unwrap();
+    writeln!(
+        html,
+        "<title>{} build guide</title>\n</head>\n<body>",
+        title
+    )
+    .unwrap();
+    writeln!(html, "<h1>{}</h1>", title).unwrap();
+    writeln!(
+        html,
+        "<p>{} by {} by {} blocks in {} layers. Each layer is shown from above, with north at the top \
+         and coordinates counted from the north west corner.</p>",
+        dx,
+        dy,
+        dz,
+        layers.len()
+    )
+    .unwrap();
+    writeln!(
+        html,
+        "<table>\n<tr><th></th><th>Block</th><th>Count</th></tr>"
+    )
+    .unwrap();
+    for (block, count) in &legend {
+        let [r, g, b] = colours[block];
+        writeln!(
+            html,
+            "<tr><td style=\"background: #{:02x}{:02x}{:02x}; width: 1em\"></td><td>{}</td><td>{}</td></tr>",
+            r,
+            g,
+            b,
+            escape(&label(block)),
+            count
+        )
+        .unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+
+    for layer in &layers {
+        let file = format!("layer_{:03}.png", layer.y);
+        let path = Path::new(dir).join(&file);
+        draw_layer(layer, &colours).write_png(&path.to_string_lossy())?;
+        writeln!(
+            html,
+            "<h2 id=\"layer-{y}\">Layer {y}</h2>\n<img src=\"{}\" alt=\"Layer {y}\">",
+            file,
+            y = layer.y
+        )
+        .unwrap();
+    }
+    writeln!(html, "</body>\n</html>").unwrap();
+
+    let index = Path::new(dir).join("index.html");
+    let index = index.to_string_lossy();
+    std::fs::write(index.as_ref(), html).map_err(|e| Error::io(&index, e))?;
+    Ok(layers.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::MinecraftVersion;
+
+    #[test]
+    fn draws_layers_with_a_legend() {
+        let stone = BlockState::new("stone");
+        let glass = BlockState::new("glass");
+        let layer = Layer {
+            y: 3,
+            rows: vec![vec![Some(&stone), None], vec![Some(&glass), Some(&stone)]],
+        };
+        let colours = maplit::hashmap! { &stone => colour(0), &glass => colour(1) };
+        let image = draw_layer(&layer, &colours);
+        let line = Image::text_height(SCALE) + MARGIN;
+        let left = MARGIN + Image::text_width("1", SCALE) + MARGIN;
+        let top = MARGIN + 2 * line;
+        let centre =
+            |x: u32, z: u32| image.get(left + x * CELL + CELL / 2, top + z * CELL + CELL / 2);
+        assert_eq!(centre(0, 0), colour(0));
+        assert_eq!(centre(1, 0), WHITE);
+        assert_eq!(centre(0, 1), colour(1));
+        // The legend's first swatch is for stone, the block used the most
+        let legend_left = left + 2 * CELL + 2 * MARGIN;
+        assert_eq!(image.get(legend_left + 1, top + 1), colour(0));
+    }
+
+    #[test]
+    fn writes_a_page_of_layers() {
+        let mut grid = VoxelGrid::new(2, 3, 2);
+        grid.set(0, 0, 0, true);
+        grid.set_block(
+            1,
+            2,
+            1,
+            BlockState::new("oak_stairs").with("facing", "north"),
+        );
+        let options = OutputOptions::new(
+            MinecraftVersion::from_data_version(2566),
+            "hut <1>",
+            BlockState::new("stone"),
+        );
+        let dir = std::env::temp_dir().join(format!("threed2vox-guide-{}", std::process::id()));
+        let dir = dir.to_string_lossy();
+        assert_eq!(write_guide(&grid, &options, &dir).unwrap(), 2);
+        let html = std::fs::read_to_string(Path::new(dir.as_ref()).join("index.html")).unwrap();
+        assert!(html.contains("<h1>hut &lt;1&gt;</h1>"));
+        assert!(html.contains("oak_stairs[facing=north]"));
+        assert!(html.contains("layer_002.png"));
+        assert!(!html.contains("layer_001.png"));
+        assert!(Path::new(dir.as_ref()).join("layer_000.png").exists());
+        std::fs::remove_dir_all(dir.as_ref()).unwrap();
+    }
+
+    #[test]
+    fn picks_distinct_colours() {
+        let colours: Vec<_> = (0..40).map(colour).collect();
+        for (i, a) in colours.iter().enumerate() {
+            assert!(!colours[i + 1..].contains(a), "{:?} is used twice", a);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Picks blocks by height, e.g. deepslate at the bottom of a mountain and snow at the top.
+
+use crate::blocks::BlockState;
+use crate::error::{Error, Result};
+use crate::mix::{hash, BlockMix};
+use crate::voxel_grid::VoxelGrid;
+use std::str::FromStr;
+
+/// Salt for deciding which side of a gradient a voxel falls on, independent of the mixes' own hashes.
+const GRADIENT_SALT: u64 = 3;
+
+/// A rule that picks each voxel's block by its Y coordinate. Each step of the rule is a `BlockMix`,
+/// so a band can itself be a mix of blocks.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeightRule {
+    /// Bands starting at the given Y coordinates, parsed from e.g. `0=deepslate;16=stone;60=snow_block`.
+    /// Voxels below the lowest band are left alone.
+    Bands(Vec<(i32, BlockMix)>),
+    /// A gradient from the bottom of the grid to the top, parsed from e.g. `deepslate;stone;andesite;snow_block`.
+    /// Voxels between two steps are randomly dithered between them, so the transition is smooth.
+    Gradient { steps: Vec<BlockMix>, seed: u64 },
+}
+
+impl HeightRule {
+    /// Parse a list of bands, e.g. `0=deepslate;16=stone:70,andesite:30`.
+    pub fn bands(s: &str) -> Result<Self> {
+        let mut bands = s
+            .split(';')
+            .filter(|band| !band.trim().is_empty())
+            .map(|band| {
+                let (y, mix) = band.split_once('=').ok_or_else(|| {
+                    Error::invalid_argument(
+                        "bands",
+                        format!("{:?} should look like Y=block", band.trim()),
+                    )
+                })?;
+                let y = y.trim().parse().map_err(|_| {
+                    Error::invalid_argument(
+                        "bands",
+                        format!("{:?} is not a Y coordinate", y.trim()),
+                    )
+                })?;
+                Ok((y, mix.parse()?))
+            })
+            .collect::<Result<Vec<(i32, BlockMix)>>>()?;
+        if bands.is_empty() {
+            return Err(Error::invalid_argument("bands", "No bands were given"));
+        }
+        bands.sort_by_key(|(y, _)| *y);
+        Ok(HeightRule::Bands(bands))
+    }
+
+    /// Parse the steps of a gradient from bottom to top, e.g. `deepslate;stone;snow_block`.
+    pub fn gradient(s: &str) -> Result<Self> {
+        let steps = s
+            .split(';')
+            .filter(|step| !step.trim().is_empty())
+            .map(BlockMix::from_str)
+            .collect::<Result<Vec<_>>>()?;
+        if steps.is_empty() {
+            return Err(Error::invalid_argument("gradient", "No blocks were given"));
+        }
+        Ok(HeightRule::Gradient { steps, seed: 0 })
+    }
+
+    /// The seed for dithering gradients. Mixes within the rule keep their own seeds.
+    pub fn seed(self, seed: u64) -> Self {
+        match self {
+            HeightRule::Gradient { steps, .. } => HeightRule::Gradient { steps, seed },
+            bands => bands,
+        }
+    }
+
+    /// Replace every mix in the rule, e.g. to validate their blocks or set their seeds.
+    pub fn try_map(self, f: impl Fn(BlockMix) -> Result<BlockMix>) -> Result<Self> {
+        Ok(match self {
+            HeightRule::Bands(bands) => HeightRule::Bands(
+                bands
+                    .into_iter()
+                    .map(|(y, mix)| Ok((y, f(mix)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            HeightRule::Gradient { steps, seed } => HeightRule::Gradient {
+                steps: steps.into_iter().map(f).collect::<Result<_>>()?,
+                seed,
+            },
+        })
+    }
+
+    /// The block at a voxel of a grid `height` voxels tall, if the rule covers it.
+    pub fn pick(&self, x: i32, y: i32, z: i32, height: i32) -> Option<&BlockState> {
+        let mix = match self {
+            HeightRule::Bands(bands) => &bands.iter().rev().find(|(min, _)| y >= *min)?.1,
+            HeightRule::Gradient { steps, seed } => {
+                let t = y as f64 / (height - 1).max(1) as f64;
+                let position = t.clamp(0.0, 1.0) * (steps.len() - 1) as f64;
+                let lower = position.floor() as usize;
+                let upper = (lower + 1).min(steps.len() - 1);
+                if hash(*seed, GRADIENT_SALT, (x, y, z)) < position - lower as f64 {
+                    &steps[upper]
+                } else {
+                    &steps[lower]
+                }
+            }
+        };
+        Some(mix.pick(x, y, z))
+    }
+
+    /// Give every filled voxel that doesn't set a block one by its height, so blocks set by other rules are kept.
+    /// Returns how many voxels were changed.
+    pub fn apply(&self, grid: &mut VoxelGrid) -> usize {
+        let height = grid.dimensions.1;
+        let changes: Vec<_> = grid
+            .filled()
+            .filter(|&(x, y, z)| grid.block(x, y, z).is_none())
+            .filter_map(|(x, y, z)| {
+                self.pick(x, y, z, height)
+                    .map(|block| ((x, y, z), block.clone()))
+            })
+            .collect();
+
+        let count = changes.len();
+        for ((x, y, z), block) in changes {
+            grid.set_block(x, y, z, block);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_blocks_by_band() {
+        let rule = HeightRule::bands("16=stone; 0=deepslate;60=snow_block").unwrap();
+        let at = |y| rule.pick(0, y, 0, 100).cloned();
+        assert_eq!(at(-1), None);
+        assert_eq!(at(0), Some(BlockState::new("deepslate")));
+        assert_eq!(at(15), Some(BlockState::new("deepslate")));
+        assert_eq!(at(16), Some(BlockState::new("stone")));
+        assert_eq!(at(99), Some(BlockState::new("snow_block")));
+
+        assert!(HeightRule::bands("stone").is_err());
+        assert!(HeightRule::bands("ten=stone").is_err());
+        assert!(HeightRule::bands("").is_err());
+    }
+
+    #[test]
+    fn blends_gradients() {
+        let rule = HeightRule::gradient("deepslate;stone;snow_block")
+            .unwrap()
+            .seed(5);
+        let count = |y, name| {
+            (0..400)
+                .filter(|&i| rule.pick(i % 20, y, i / 20, 21) == Some(&BlockState::new(name)))
+                .count()
+        };
+        assert_eq!(count(0, "deepslate"), 400);
+        assert_eq!(count(10, "stone"), 400);
+        assert_eq!(count(20, "snow_block"), 400);
+        // Halfway between two steps, about half the voxels get each
+        let deepslate = count(5, "deepslate");
+        assert!((150..250).contains(&deepslate), "{}", deepslate);
+        assert_eq!(deepslate + count(5, "stone"), 400);
+        // The same seed gives the same blocks
+        let again = HeightRule::gradient("deepslate;stone;snow_block")
+            .unwrap()
+            .seed(5);
+        assert!((0..20).all(|y| rule.pick(3, y, 4, 21) == again.pick(3, y, 4, 21)));
+    }
+
+    #[test]
+    fn keeps_blocks_set_by_other_rules() {
+        let mut grid = VoxelGrid::new(1, 3, 1);
+        grid.set(0, 0, 0, true);
+        grid.set(0, 1, 0, true);
+        grid.set_block(0, 2, 0, BlockState::new("glass"));
+        let rule = HeightRule::bands("1=stone").unwrap();
+        assert_eq!(rule.apply(&mut grid), 1);
+        assert_eq!(grid.block(0, 0, 0), None);
+        assert_eq!(grid.block(0, 1, 0), Some(&BlockState::new("stone")));
+        assert_eq!(grid.block(0, 2, 0), Some(&BlockState::new("glass")));
+    }
+}
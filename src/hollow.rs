@@ -0,0 +1,138 @@
+//! Hollows out solid models down to walls of a given thickness, leaving pillars inside to hold up the roof.
+//! Check the result with `buildability`, e.g. for sand left over a hollowed out room.
+
+use crate::voxel_grid::VoxelGrid;
+use std::collections::HashMap;
+
+const FACES: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+/// Hollows a model, keeping a shell `thickness` voxels thick and, every `support_spacing` voxels along X and Z,
+/// a one voxel wide pillar through the inside.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hollow {
+    pub thickness: i32,
+    /// How far apart the pillars are, or `None` for no pillars
+    pub support_spacing: Option<i32>,
+}
+
+impl Hollow {
+    pub fn new(thickness: i32) -> Self {
+        Self {
+            thickness,
+            support_spacing: None,
+        }
+    }
+
+    pub fn support_spacing(mut self, spacing: Option<i32>) -> Self {
+        self.support_spacing = spacing;
+        self
+    }
+
+    fn is_support(&self, x: i32, z: i32) -> bool {
+        self.support_spacing
+            .is_some_and(|s| x.rem_euclid(s) == 0 && z.rem_euclid(s) == 0)
+    }
+
+    /// Fill the inside of the model, then empty every voxel deeper than the wall thickness that isn't part of a
+    /// pillar. Returns how many voxels were emptied and how many were kept as pillars.
+    pub fn apply(&self, grid: &mut VoxelGrid) -> (usize, usize) {
+        grid.fill_interior();
+        let depths = depths(grid);
+        let mut emptied = 0;
+        let mut supports = 0;
+        for (&(x, y, z), &depth) in &depths {
+            if depth <= self.thickness {
+                continue;
+            }
+            if self.is_support(x, z) {
+                supports += 1;
+            } else {
+                grid.set(x, y, z, false);
+                emptied += 1;
+            }
+        }
+        (emptied, supports)
+    }
+}
+
+/// How many steps through faces each filled voxel is from an empty voxel, starting at 1 for the voxels on the
+/// model's surface. Everything outside the grid counts as empty.
+fn depths(grid: &VoxelGrid) -> HashMap<(i32, i32, i32), i32> {
+    let is_filled = |(x, y, z): (i32, i32, i32)| grid.contains(x, y, z) && *grid.get(x, y, z);
+    let mut depths = HashMap::new();
+    let mut frontier: Vec<_> = grid
+        .filled()
+        .filter(|&(x, y, z)| {
+            FACES
+                .iter()
+                .any(|(i, j, k)| !is_filled((x + i, y + j, z + k)))
+        })
+        .collect();
+    for &voxel in &frontier {
+        depths.insert(voxel, 1);
+    }
+    let mut depth = 1;
+    while !frontier.is_empty() {
+        depth += 1;
+        let mut next = Vec::new();
+        for (x, y, z) in frontier {
+            for (i, j, k) in &FACES {
+                let voxel = (x + i, y + j, z + k);
+                if is_filled(voxel) && !depths.contains_key(&voxel) {
+                    depths.insert(voxel, depth);
+                    next.push(voxel);
+                }
+            }
+        }
+        frontier = next;
+    }
+    depths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A closed 9x5x9 box with walls one voxel thick, like the voxelizer's shells.
+    fn shell() -> VoxelGrid {
+        VoxelGrid::from_fn((9, 5, 9), |x, y, z| x % 8 == 0 || y % 4 == 0 || z % 8 == 0)
+    }
+
+    #[test]
+    fn hollows_to_the_wall_thickness() {
+        let mut grid = shell();
+        let (emptied, supports) = Hollow::new(2).apply(&mut grid);
+        assert_eq!(supports, 0);
+        // The inside is 5x1x5 once the walls are 2 thick
+        assert_eq!(emptied, 5 * 5);
+        assert!(*grid.get(1, 1, 1));
+        assert!(!grid.get(4, 2, 4));
+    }
+
+    #[test]
+    fn leaves_pillars_inside() {
+        let mut grid = shell();
+        let (_, supports) = Hollow::new(1).support_spacing(Some(4)).apply(&mut grid);
+        // One pillar in the middle, from the floor to the ceiling
+        assert_eq!(supports, 3);
+        assert!((1..4).all(|y| *grid.get(4, y, 4)));
+        assert!(!grid.get(3, 2, 4));
+    }
+
+    #[test]
+    fn leaves_open_shells_open() {
+        // Without the roof, there's no inside to fill and hollow again
+        let mut grid = VoxelGrid::from_fn((9, 4, 9), |x, y, z| x % 8 == 0 || y == 0 || z % 8 == 0);
+        let (emptied, _) = Hollow::new(1).apply(&mut grid);
+        assert_eq!(emptied, 0);
+        assert_eq!(grid.filled().count(), 9 * 9 + 32 * 3);
+        assert!(!grid.get(4, 3, 4));
+    }
+}
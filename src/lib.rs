@@ -1,208 +1,226 @@
-use config::{Config, VoxelOption};
+use config::{reader_for_extension, Config};
+pub use error::{Error, Result};
+use progress::Reporter;
+use std::path::Path;
 use voxel_grid::VoxelGrid;
 
-use nalgebra::{Translation3, Vector3};
-use parry3d::na::Isometry3;
-use parry3d::query::{
-    ContactManifold, ContactManifoldsWorkspace, DefaultQueryDispatcher, PersistentQueryDispatcher,
-};
-use parry3d::shape::{Cuboid, TriMesh};
-use rayon::prelude::*;
-use std::sync::Mutex;
+pub use parry3d::shape::TriMesh;
 
+pub mod blocks;
+pub mod boolean;
+pub mod buildability;
+pub mod components;
 pub mod config;
+pub mod error;
+pub mod guide;
+pub mod height;
+pub mod hollow;
+pub mod materials;
+pub mod mix;
+pub mod morphology;
 mod nbt_helper;
 pub mod nbtifier;
+pub mod orient;
+pub mod preview;
+pub mod progress;
+mod raster;
 pub mod readers;
+pub mod scene;
+pub mod slice;
+pub mod smooth;
+pub mod transform;
+pub mod version;
 pub mod voxel_grid;
+pub mod voxelizer;
+pub mod water;
 
 /// Read object from path and step through it with a given voxel size.
-pub fn to_schematic(config: Config) -> anyhow::Result<nbt::Blob> {
-    log::info!("Loading model.");
-    let trimesh = config.reader.load(&config.input_path)?;
-
-    let mut trimesh_transform =
-        Isometry3::rotation(Vector3::new(config.x_rot, config.y_rot, config.z_rot));
-    let mins = trimesh.aabb(&trimesh_transform).mins;
-    trimesh_transform.translation = Translation3::new(-mins.x, -mins.y, -mins.z);
-
-    let voxel_size = match config.voxel_size {
-        VoxelOption::VoxelSize(s) => s,
-        VoxelOption::MeshSize(s) => {
-            let aabb = trimesh.aabb(&trimesh_transform);
-            let extents = aabb.extents();
-            let longest = if extents.x > extents.y {
-                extents.x
-            } else {
-                extents.y
-            };
-            let longest = if longest > extents.z {
-                longest
-            } else {
-                extents.z
-            };
-
-            longest / s
-        }
-    };
-
-    // Determine the voxel grid size
-    let aabb = trimesh.aabb(&trimesh_transform);
-    let extents = aabb.extents();
-    let x = f32::ceil(extents.x / voxel_size) as i32 + 1;
-    let y = f32::ceil(extents.y / voxel_size) as i32 + 1;
-    let z = f32::ceil(extents.z / voxel_size) as i32 + 1;
-
-    let mut grid = VoxelGrid::new(x, y, z);
-
-    // Iterate over voxels and do collision tests
-    log::info!(
-        "Dimensions of the model are {}x{}x{}. Starting conversion.",
-        x,
-        y,
-        z
-    );
-
-    let results = match cfg!(feature = "sequential") {
-        true => do_collision_seq((x, y, z), voxel_size, &trimesh, &trimesh_transform),
-        false => do_collision_par((x, y, z), voxel_size, &trimesh, &trimesh_transform),
-    };
-
-    results
-        .into_iter()
-        .for_each(|(i, j, k)| grid.set(i, j, k, true));
-
-    Ok(config.nbtify.convert(&grid, &config).unwrap())
+pub fn to_schematic(config: Config) -> Result<nbt::Blob> {
+    let grid = to_voxel_grid(&config)?;
+    let options = config.output_options();
+    if let Some(path) = &config.materials_path {
+        let bill =
+            materials::BillOfMaterials::count(&grid, &options.block, config.materials_by_layer);
+        bill.write(path)?;
+        log::info!("Wrote the bill of materials to '{}'.", path);
+    }
+    if let Some(dir) = &config.guide_path {
+        let layers = guide::write_guide(&grid, &options, dir)?;
+        log::info!("Wrote a build guide of {} layers to '{}'.", layers, dir);
+    }
+    if let Some(path) = &config.preview_path {
+        preview::write_preview(&grid, &options, config.preview_view, path)?;
+        log::info!("Wrote a preview to '{}'.", path);
+    }
+    config.nbtify.convert(&grid, &options)
 }
 
-/// The inner part of do_collision_*
-fn actually_do_collision(
-    xyz: (i32, i32, i32),
-    voxel_size: f32,
-    voxel: &Cuboid,
-    trimesh: &TriMesh,
-    pos: &Isometry3<f32>,
-    manifold: &mut Vec<ContactManifold<(), ()>>,
-    workspace: &mut Option<ContactManifoldsWorkspace>,
-) -> Option<(i32, i32, i32)> {
-    let (x, y, z) = xyz;
-    let voxel_half = voxel_size / 2.0;
-
-    let transform = Isometry3::translation(
-        (x as f32) * voxel_size - voxel_half,
-        (y as f32) * voxel_size - voxel_half,
-        (z as f32) * voxel_size - voxel_half,
+/// Start reporting a post-processing stage of `steps` steps, unless the conversion was cancelled.
+fn stage<'a>(config: &'a Config, name: &'static str, steps: usize) -> Result<Reporter<'a>> {
+    let reporter = Reporter::new(
+        name,
+        steps,
+        config.progress.as_ref(),
+        config.cancellation.as_ref(),
     );
-
-    let dispatch = DefaultQueryDispatcher;
-
-    // See source code for contact_shape_shape for why
-    let pos12 = transform.inv_mul(pos);
-
-    dispatch
-        .contact_manifolds(&pos12, voxel, trimesh, 0.0, manifold, workspace)
-        .unwrap();
-
-    match manifold.len() {
-        0 => None,
-        _ => Some((x, y, z)),
+    if reporter.is_cancelled() {
+        return Err(Error::Cancelled);
     }
+    Ok(reporter)
 }
 
-fn do_collision_par(
-    xyz: (i32, i32, i32),
-    voxel_size: f32,
-    trimesh: &TriMesh,
-    pos: &Isometry3<f32>,
-) -> Vec<(i32, i32, i32)> {
-    let (x, y, z) = xyz;
-    let voxel_half = voxel_size / 2.0;
-    let voxel = Cuboid::new(Vector3::new(voxel_half, voxel_half, voxel_half));
-
-    let progress = Mutex::new(0);
-    (0..x)
-        .into_par_iter()
-        .map(|i| {
-            let result = (0..y)
-                .into_par_iter()
-                .map(|j| {
-                    let mut range = (0..z).peekable();
-                    let mut z_range = Vec::new();
-                    while range.peek().is_some() {
-                        let chunk: Vec<_> = range.by_ref().take(100).collect();
-                        z_range.push(chunk);
-                    }
-                    z_range
-                        .into_par_iter()
-                        .map(|ks| {
-                            let mut manifolds = Vec::new();
-                            let mut workspace = None;
-                            let mut output = Vec::new();
-                            for k in ks {
-                                if let Some(o) = actually_do_collision(
-                                    (i, j, k),
-                                    voxel_size,
-                                    &voxel,
-                                    trimesh,
-                                    pos,
-                                    &mut manifolds,
-                                    &mut workspace,
-                                ) {
-                                    output.push(o);
-                                }
-                            }
-                            output
-                        })
-                        .flatten()
-                        .collect::<Vec<_>>()
-                })
-                .flatten()
-                .collect::<Vec<_>>();
-            let mut p = progress.lock().unwrap();
-            *p += 1;
-            log::info!("Progress {:.2}%", (*p as f32) / (x as f32) * 100.0);
-            result
-        })
-        .flatten()
-        .collect()
-}
-
-fn do_collision_seq(
-    xyz: (i32, i32, i32),
-    voxel_size: f32,
-    trimesh: &TriMesh,
-    pos: &Isometry3<f32>,
-) -> Vec<(i32, i32, i32)> {
-    let (x, y, z) = xyz;
-    let voxel_half = voxel_size / 2.0;
-    let voxel = Cuboid::new(Vector3::new(voxel_half, voxel_half, voxel_half));
-
-    let mut manifold = Vec::new();
-    let mut workspace = None;
-
-    (0..x)
-        .map(|i| {
-            let result = (0..y)
-                .map(|j| {
-                    (0..z)
-                        .filter_map(|k| {
-                            actually_do_collision(
-                                (i, j, k),
-                                voxel_size,
-                                &voxel,
-                                trimesh,
-                                pos,
-                                &mut manifold,
-                                &mut workspace,
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .flatten()
-                .collect::<Vec<_>>();
-            log::info!("Progress {:.2}%", (i as f32) / (x as f32) * 100.0);
-            result
-        })
-        .flatten()
-        .collect()
+/// Voxelise the input and apply every post-processing step in the config, without writing the output.
+/// The grid's origin and voxel size say where it is, so grids from separate calls can be combined with `boolean`.
+/// Each step reports its progress to the config's callback, and checks its cancellation token before it starts.
+pub fn to_voxel_grid(config: &Config) -> Result<VoxelGrid> {
+    let mut grid = match &config.scene {
+        Some(scene) => scene.voxelize_with(
+            config.voxel_size,
+            config.metres_per_unit,
+            config.report_to(voxelizer::Voxelizer::new()),
+        )?,
+        None => {
+            log::info!("Loading model.");
+            let (trimesh, parts) = config.reader.load_parts(&config.input_path)?;
+            config.voxelizer()?.parts(parts).voxelize(trimesh)?
+        }
+    };
+    if !config.combine.is_empty() {
+        let reporter = stage(config, "Combining", config.combine.len())?;
+        // The models are shells, so carving one out of another needs their insides
+        grid.fill_interior();
+        for (op, path) in &config.combine {
+            if reporter.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            log::info!("Loading {} to {:?} with the model.", path, op);
+            let extension = Path::new(path).extension().unwrap_or_default();
+            let reader = reader_for_extension(&extension.to_string_lossy())?;
+            let (trimesh, parts) = reader.load_parts(path)?;
+            let mut other = config
+                .voxelizer_for(reader.as_ref(), path)?
+                .voxel_size(voxelizer::VoxelOption::VoxelSize(grid.voxel_size))
+                .parts(parts)
+                .voxelize(trimesh)?;
+            other.fill_interior();
+            let changed = boolean::combine(&mut grid, &other, *op)?;
+            log::info!("{:?} changed {} voxels.", op, changed);
+            reporter.step();
+        }
+    }
+    if !config.morphology.is_empty() {
+        let reporter = stage(config, "Dilating and eroding", config.morphology.len())?;
+        for step in &config.morphology {
+            if reporter.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let (filled, emptied) = step.apply(&mut grid);
+            log::info!(
+                "{:?}: filled {} voxels and emptied {}.",
+                step.operation,
+                filled,
+                emptied
+            );
+            reporter.step();
+        }
+    }
+    if config.min_component_size.is_some() || config.keep_largest || config.report_floating {
+        let reporter = stage(config, "Finding separate pieces", 1)?;
+        if let Some(min_size) = config.min_component_size {
+            let removed =
+                components::remove_small_components(&mut grid, config.connectivity, min_size);
+            log::info!(
+                "Removed {} voxels in pieces smaller than {}.",
+                removed,
+                min_size
+            );
+        }
+        if config.keep_largest {
+            let removed = components::keep_largest_component(&mut grid, config.connectivity);
+            log::info!("Removed {} voxels outside the largest piece.", removed);
+        }
+        if config.report_floating {
+            for component in components::floating_components(&grid, config.connectivity) {
+                let (min, max) = component.bounds();
+                log::warn!(
+                    "A piece of {} voxels from {:?} to {:?} floats in the air.",
+                    component.len(),
+                    min,
+                    max
+                );
+            }
+        }
+        reporter.step();
+    }
+    if let Some(hollow) = &config.hollow {
+        let reporter = stage(config, "Hollowing", 1)?;
+        let (emptied, supports) = hollow.apply(&mut grid);
+        log::info!(
+            "Hollowed out {} voxels, leaving {} for pillars.",
+            emptied,
+            supports
+        );
+        reporter.step();
+    }
+    let reporter = stage(config, "Picking blocks", 1)?;
+    if let Some(rule) = &config.height_rule {
+        let banded = rule.apply(&mut grid);
+        log::info!("Picked {} blocks by height.", banded);
+    }
+    if let Some(mix) = &config.block_mix {
+        mix.apply(&mut grid);
+    }
+    if config.orient {
+        let oriented = orient::orient_blocks(&mut grid, &config.block, &config.blocks);
+        log::info!("Oriented {} blocks to follow the surface.", oriented);
+    }
+    reporter.step();
+    if config.smooth {
+        let reporter = stage(config, "Smoothing", 1)?;
+        let smoothed =
+            smooth::smooth_surface(&mut grid, &config.block, &config.blocks, &config.version);
+        log::info!("Replaced {} blocks with stairs and slabs.", smoothed);
+        reporter.step();
+    }
+    if !config.water_parts.is_empty() || config.water_level.is_some() {
+        let reporter = stage(config, "Filling water", 1)?;
+        let filled = water::fill_water(&mut grid, &config.water_parts, config.water_level);
+        log::info!("Filled {} voxels with water.", filled);
+        let waterlogged = water::waterlog_blocks(&mut grid, &config.block, &config.blocks);
+        log::info!("Waterlogged {} blocks.", waterlogged);
+        reporter.step();
+    }
+    let reporter = stage(config, "Checking buildability", 1)?;
+    let report =
+        buildability::BuildReport::check(&grid, &config.block, &config.blocks, config.max_overhang);
+    if !report.falling.is_empty() {
+        log::warn!(
+            "{} blocks like sand or gravel have nothing under them and will fall when built, e.g. at {:?}.",
+            report.falling.len(),
+            report.falling[0].position
+        );
+    }
+    if !report.detached.is_empty() {
+        log::warn!(
+            "{} blocks like torches or buttons have nothing to attach to and will pop off, e.g. at {:?}.",
+            report.detached.len(),
+            report.detached[0].position
+        );
+    }
+    if !report.overhangs.is_empty() {
+        log::warn!(
+            "{} overhangs reach further than {} blocks, e.g. in layer {} from {:?} to {:?}.",
+            report.overhangs.len(),
+            config.max_overhang.unwrap_or_default(),
+            report.overhangs[0].layer,
+            report.overhangs[0].min,
+            report.overhangs[0].max
+        );
+    }
+    if let Some(path) = &config.report_path {
+        report.write(path)?;
+        log::info!("Wrote the buildability report to '{}'.", path);
+    }
+    reporter.step();
+    Ok(grid)
 }
@@ -0,0 +1,287 @@
+//! Counts the blocks needed to build the output, e.g. so survival builders know what to gather, in full stacks
+//! and shulker boxes, as text, CSV or JSON.
+
+use crate::blocks::BlockState;
+use crate::error::{Error, Result};
+use crate::voxel_grid::VoxelGrid;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How many blocks fit in a stack.
+pub const STACK_SIZE: usize = 64;
+
+/// How many stacks fit in a shulker box.
+pub const SHULKER_BOX_STACKS: usize = 27;
+
+/// Blocks that can't be gathered as items, and aren't counted.
+const NOT_ITEMS: [&str; 5] = [
+    "minecraft:air",
+    "minecraft:cave_air",
+    "minecraft:void_air",
+    "minecraft:water",
+    "minecraft:lava",
+];
+
+/// The item to place a block with, and how many of them it takes, or `None` if it can't be placed from an item.
+fn item(block: &BlockState) -> Option<(String, usize)> {
+    if NOT_ITEMS.contains(&block.name.as_str()) {
+        return None;
+    }
+    // Wall torches are placed with the torch item
+    let name = block.name.replace("wall_torch", "torch");
+    let count = match block.get("type") {
+        Some("double") if name.ends_with("_slab") => 2,
+        _ => 1,
+    };
+    Some((name, count))
+}
+
+/// How many of one item to gather.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Material {
+    pub item: String,
+    pub count: usize,
+    /// Full stacks of 64
+    pub stacks: usize,
+    /// Items left over after the full stacks
+    pub remainder: usize,
+    /// Shulker boxes needed to carry them all
+    pub shulker_boxes: usize,
+}
+
+impl Material {
+    pub fn new(item: &str, count: usize) -> Self {
+        Self {
+            item: item.to_string(),
+            count,
+            stacks: count / STACK_SIZE,
+            remainder: count % STACK_SIZE,
+            shulker_boxes: count.div_ceil(STACK_SIZE * SHULKER_BOX_STACKS),
+        }
+    }
+}
+
+/// The materials for one Y layer of the output.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Layer {
+    pub y: i32,
+    pub materials: Vec<Material>,
+}
+
+/// The materials for the whole output, most needed first, and optionally for each layer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BillOfMaterials {
+    pub total: Vec<Material>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<Layer>,
+}
+
+fn materials(counts: HashMap<String, usize>) -> Vec<Material> {
+    let mut materials: Vec<_> = counts
+        .into_iter()
+        .map(|(item, count)| Material::new(&item, count))
+        .collect();
+    materials.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.item.cmp(&b.item)));
+    materials
+}
+
+impl BillOfMaterials {
+    /// Count the blocks in the grid. Voxels without a block use `default`.
+    pub fn count(grid: &VoxelGrid, default: &BlockState, by_layer: bool) -> Self {
+        let mut total = HashMap::new();
+        let mut layers: BTreeMap<i32, HashMap<String, usize>> = BTreeMap::new();
+        for (x, y, z) in grid.filled() {
+            let block = grid.block(x, y, z).unwrap_or(default);
+            if let Some((item, count)) = item(block) {
+                if by_layer {
+                    *layers
+                        .entry(y)
+                        .or_default()
+                        .entry(item.clone())
+                        .or_default() += count;
+                }
+                *total.entry(item).or_default() += count;
+            }
+        }
+        Self {
+            total: materials(total),
+            layers: layers
+                .into_iter()
+                .map(|(y, counts)| Layer {
+                    y,
+                    materials: materials(counts),
+                })
+                .collect(),
+        }
+    }
+
+    /// A table for reading, with a table for each layer after the total.
+    pub fn to_text(&self) -> String {
+        let table = |out: &mut String, materials: &[Material]| {
+            let width = materials
+                .iter()
+                .map(|m| m.item.len())
+                .chain(Some("Block".len()))
+                .max()
+                .unwrap();
+            writeln!(
+                out,
+                "{:width$}  {:>8}  {:>14}  {:>13}",
+                "Block",
+                "Count",
+                "Stacks",
+                "Shulker boxes",
+                width = width
+            )
+            .unwrap();
+            for m in materials {
+                writeln!(
+                    out,
+                    "{:width$}  {:>8}  {:>14}  {:>13}",
+                    m.item,
+                    m.count,
+                    format!("{} + {}", m.stacks, m.remainder),
+                    m.shulker_boxes,
+                    width = width
+                )
+                .unwrap();
+            }
+        };
+        let mut out = String::new();
+        table(&mut out, &self.total);
+        for layer in &self.layers {
+            writeln!(out, "\nLayer {}", layer.y).unwrap();
+            table(&mut out, &layer.materials);
+        }
+        out
+    }
+
+    /// One row per item, or per item and layer if the layers were counted, followed by the totals.
+    pub fn to_csv(&self) -> String {
+        let row = |m: &Material| {
+            format!(
+                "{},{},{},{},{}",
+                m.item, m.count, m.stacks, m.remainder, m.shulker_boxes
+            )
+        };
+        let mut out = String::new();
+        if self.layers.is_empty() {
+            writeln!(out, "item,count,stacks,remainder,shulker_boxes").unwrap();
+            for m in &self.total {
+                writeln!(out, "{}", row(m)).unwrap();
+            }
+        } else {
+            writeln!(out, "layer,item,count,stacks,remainder,shulker_boxes").unwrap();
+            for layer in &self.layers {
+                for m in &layer.materials {
+                    writeln!(out, "{},{}", layer.y, row(m)).unwrap();
+                }
+            }
+            for m in &self.total {
+                writeln!(out, "total,{}", row(m)).unwrap();
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Materials are always valid JSON")
+    }
+
+    /// Write the bill of materials to a file, in the format its extension names, or as text for other extensions.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let extension = Path::new(path).extension().unwrap_or_default();
+        let format: MaterialsFormat = extension
+            .to_str()
+            .unwrap_or_default()
+            .parse()
+            .unwrap_or(MaterialsFormat::Text);
+        let content = match format {
+            MaterialsFormat::Text => self.to_text(),
+            MaterialsFormat::Csv => self.to_csv(),
+            MaterialsFormat::Json => self.to_json(),
+        };
+        std::fs::write(path, content).map_err(|e| Error::io(path, e))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialsFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl FromStr for MaterialsFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "txt" | "text" => Ok(MaterialsFormat::Text),
+            "csv" => Ok(MaterialsFormat::Csv),
+            "json" => Ok(MaterialsFormat::Json),
+            other => Err(Error::invalid_argument(
+                "materials",
+                format!("{:?} should be txt, csv or json", other),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 100 stone in the bottom layer, a double slab and a wall torch on top of it.
+    fn grid() -> VoxelGrid {
+        let mut grid = VoxelGrid::from_fn((10, 2, 10), |_, y, _| y == 0);
+        grid.set_block(0, 1, 0, BlockState::new("oak_slab").with("type", "double"));
+        grid.set_block(1, 1, 0, BlockState::new("wall_torch"));
+        grid.set_block(2, 1, 0, BlockState::new("water"));
+        grid
+    }
+
+    #[test]
+    fn counts_items() {
+        let bill = BillOfMaterials::count(&grid(), &BlockState::new("stone"), false);
+        assert_eq!(
+            bill.total,
+            vec![
+                Material::new("minecraft:stone", 100),
+                Material::new("minecraft:oak_slab", 2),
+                Material::new("minecraft:torch", 1),
+            ]
+        );
+        assert!(bill.layers.is_empty());
+        assert_eq!(bill.total[0].stacks, 1);
+        assert_eq!(bill.total[0].remainder, 36);
+        assert_eq!(bill.total[0].shulker_boxes, 1);
+        assert_eq!(Material::new("stone", 1728 * 2 + 1).shulker_boxes, 3);
+    }
+
+    #[test]
+    fn counts_layers() {
+        let bill = BillOfMaterials::count(&grid(), &BlockState::new("stone"), true);
+        assert_eq!(bill.layers.len(), 2);
+        assert_eq!(bill.layers[1].y, 1);
+        assert_eq!(bill.layers[1].materials.len(), 2);
+
+        let csv = bill.to_csv();
+        assert!(csv.starts_with("layer,item,count"));
+        assert!(csv.contains("0,minecraft:stone,100,1,36,1\n"));
+        assert!(csv.contains("total,minecraft:torch,1,0,1,1\n"));
+        assert!(bill.to_text().contains("Layer 1"));
+    }
+
+    #[test]
+    fn writes_json() {
+        let bill = BillOfMaterials::count(&grid(), &BlockState::new("stone"), false);
+        let json: serde_json::Value = serde_json::from_str(&bill.to_json()).unwrap();
+        assert_eq!(json["total"][0]["item"], "minecraft:stone");
+        assert_eq!(json["total"][0]["count"], 100);
+        assert!(json.get("layers").is_none());
+    }
+}
@@ -0,0 +1,316 @@
+//! Weighted mixes of blocks, e.g. `stone:60,andesite:25,cobblestone:15`, so large surfaces aren't a single block.
+
+use crate::blocks::BlockState;
+use crate::error::{Error, Result};
+use crate::voxel_grid::VoxelGrid;
+use std::str::FromStr;
+
+/// Salts that keep the hashes for picking feature points and blocks independent.
+const POINT_SALT: u64 = 1;
+const BLOCK_SALT: u64 = 2;
+
+/// Scramble the bits of `z`, from the SplitMix64 generator.
+fn split_mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A number in [0, 1) that only depends on the seed, the salt and the coordinates.
+pub(crate) fn hash(seed: u64, salt: u64, (x, y, z): (i32, i32, i32)) -> f64 {
+    let h = [x, y, z].iter().fold(split_mix(seed ^ salt), |h, &v| {
+        split_mix(h ^ v as u32 as u64)
+    });
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A weighted choice between blocks. Parsed from a comma-separated list of blocks, each optionally followed by a
+/// `:weight`, e.g. `stone:60,andesite:25,cobblestone:15`. Blocks without a weight have a weight of 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockMix {
+    blocks: Vec<(BlockState, f64)>,
+    seed: u64,
+    patch_size: Option<f32>,
+}
+
+impl BlockMix {
+    /// A mix of a single block.
+    pub fn new(block: BlockState) -> Self {
+        Self {
+            blocks: vec![(block, 1.0)],
+            seed: 0,
+            patch_size: None,
+        }
+    }
+
+    /// The seed for picking blocks. The same seed always gives the same blocks.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Group blocks into patches about this many voxels across, instead of picking each voxel's block independently.
+    pub fn patch_size(mut self, patch_size: f32) -> Self {
+        self.patch_size = Some(patch_size);
+        self
+    }
+
+    /// The blocks in the mix and their weights.
+    pub fn blocks(&self) -> impl Iterator<Item = (&BlockState, f64)> {
+        self.blocks.iter().map(|(block, weight)| (block, *weight))
+    }
+
+    /// Replace every block in the mix, e.g. with the validated version of it.
+    pub fn try_map(self, f: impl Fn(BlockState) -> Result<BlockState>) -> Result<Self> {
+        let blocks = self
+            .blocks
+            .into_iter()
+            .map(|(block, weight)| Ok((f(block)?, weight)))
+            .collect::<Result<_>>()?;
+        Ok(Self { blocks, ..self })
+    }
+
+    /// The block with the largest weight, or the first of them if several are equally heavy.
+    pub fn main_block(&self) -> &BlockState {
+        let mut main = &self.blocks[0];
+        for entry in &self.blocks[1..] {
+            if entry.1 > main.1 {
+                main = entry;
+            }
+        }
+        &main.0
+    }
+
+    /// Whether the mix only has one block in it.
+    pub fn is_single(&self) -> bool {
+        self.blocks.len() == 1
+    }
+
+    /// The cell whose block a voxel takes. Without patches, every voxel is its own cell. With patches, the grid is
+    /// split into cells of `patch_size` with a random point in each, and voxels join the cell of the nearest point.
+    fn cell(&self, pos: (i32, i32, i32)) -> (i32, i32, i32) {
+        let size = match self.patch_size {
+            Some(size) if size > 1.0 => size,
+            _ => return pos,
+        };
+        let p = [pos.0 as f32, pos.1 as f32, pos.2 as f32];
+        let home = [
+            (p[0] / size).floor() as i32,
+            (p[1] / size).floor() as i32,
+            (p[2] / size).floor() as i32,
+        ];
+        let mut nearest = (f32::INFINITY, pos);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let cell = (home[0] + dx, home[1] + dy, home[2] + dz);
+                    let offset = |axis: u64, c: i32| {
+                        (c as f32 + hash(self.seed, POINT_SALT + axis * 16, cell) as f32) * size
+                    };
+                    let point = [offset(0, cell.0), offset(1, cell.1), offset(2, cell.2)];
+                    let distance: f32 = (0..3).map(|i| (point[i] - p[i]).powi(2)).sum();
+                    if distance < nearest.0 {
+                        nearest = (distance, cell);
+                    }
+                }
+            }
+        }
+        nearest.1
+    }
+
+    /// The block at a voxel.
+    pub fn pick(&self, x: i32, y: i32, z: i32) -> &BlockState {
+        let total: f64 = self.blocks.iter().map(|(_, weight)| weight).sum();
+        let mut choice = hash(self.seed, BLOCK_SALT, self.cell((x, y, z))) * total;
+        for (block, weight) in &self.blocks {
+            if choice < *weight {
+                return block;
+            }
+            choice -= weight;
+        }
+        &self.blocks[self.blocks.len() - 1].0
+    }
+
+    /// Give every filled voxel that doesn't set a block one from the mix. Returns how many voxels were changed.
+    pub fn apply(&self, grid: &mut VoxelGrid) -> usize {
+        let changes: Vec<_> = grid
+            .filled()
+            .filter(|&(x, y, z)| grid.block(x, y, z).is_none())
+            .collect();
+        for &(x, y, z) in &changes {
+            grid.set_block(x, y, z, self.pick(x, y, z).clone());
+        }
+        changes.len()
+    }
+}
+
+/// Give filled voxels that a named part of the mesh passes through a block from that part's mix, e.g. glass for a
+/// material named "window". Names match a part or its material, ignoring case, and the first match wins.
+/// Returns how many voxels were changed.
+pub fn map_materials(grid: &mut VoxelGrid, mapping: &[(String, BlockMix)]) -> usize {
+    let changes: Vec<_> = grid
+        .filled()
+        .filter_map(|(x, y, z)| {
+            let parts = grid.parts_at(x, y, z);
+            mapping
+                .iter()
+                .find(|(name, _)| parts.iter().any(|&i| grid.parts()[i].is_named(name)))
+                .map(|(_, mix)| ((x, y, z), mix.pick(x, y, z).clone()))
+        })
+        .collect();
+
+    let count = changes.len();
+    for ((x, y, z), block) in changes {
+        grid.set_block(x, y, z, block);
+    }
+    count
+}
+
+/// Split a list on commas, except those inside a block state's properties.
+fn split_entries(s: &str) -> Vec<&str> {
+    let mut entries = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                entries.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    entries.push(&s[start..]);
+    entries
+}
+
+impl FromStr for BlockMix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let blocks = split_entries(s)
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.trim();
+                // The weight is after the last colon, which may also separate the namespace, e.g. minecraft:stone:60
+                let properties_end = entry.rfind(']').unwrap_or(0);
+                let weight = entry
+                    .rfind(':')
+                    .filter(|&i| i > properties_end)
+                    .map(|i| (&entry[..i], &entry[i + 1..]))
+                    .filter(|(_, weight)| {
+                        weight
+                            .trim()
+                            .starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-')
+                    });
+                let (block, weight) = match weight {
+                    Some((block, weight)) => {
+                        let weight: f64 = weight.trim().parse().map_err(|_| {
+                            Error::invalid_argument(
+                                "block",
+                                format!("{:?} is not a valid weight", weight),
+                            )
+                        })?;
+                        (block, weight)
+                    }
+                    None => (entry, 1.0),
+                };
+                if !(weight.is_finite() && weight > 0.0) {
+                    return Err(Error::invalid_argument(
+                        "block",
+                        format!("The weight of {} must be positive", block),
+                    ));
+                }
+                Ok((block.parse()?, weight))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            blocks,
+            seed: 0,
+            patch_size: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mix() -> BlockMix {
+        "stone:60,andesite:25,cobblestone:15".parse().unwrap()
+    }
+
+    /// How many voxels of a 20x20x20 cube get each block of the mix.
+    fn counts(mix: &BlockMix) -> Vec<usize> {
+        let mut counts = vec![0; mix.blocks.len()];
+        for x in 0..20 {
+            for y in 0..20 {
+                for z in 0..20 {
+                    let block = mix.pick(x, y, z);
+                    counts[mix.blocks.iter().position(|(b, _)| b == block).unwrap()] += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn parses_weighted_blocks() {
+        let mix: BlockMix = "minecraft:oak_log[axis=y,]:3, stone, birch_planks:0.5"
+            .parse()
+            .unwrap();
+        let blocks: Vec<_> = mix.blocks().collect();
+        assert_eq!(
+            blocks[0],
+            (&BlockState::new("oak_log").with("axis", "y"), 3.0)
+        );
+        assert_eq!(blocks[1], (&BlockState::new("stone"), 1.0));
+        assert_eq!(blocks[2], (&BlockState::new("birch_planks"), 0.5));
+        assert_eq!(
+            mix.main_block(),
+            &BlockState::new("oak_log").with("axis", "y")
+        );
+
+        assert!("stone:0".parse::<BlockMix>().is_err());
+        assert!("stone:-1".parse::<BlockMix>().is_err());
+        assert!("stone:1x".parse::<BlockMix>().is_err());
+        assert!("stone".parse::<BlockMix>().unwrap().is_single());
+    }
+
+    #[test]
+    fn follows_the_weights() {
+        let counts = counts(&mix());
+        let total = 20.0 * 20.0 * 20.0;
+        for (count, weight) in counts.iter().zip(&[0.6, 0.25, 0.15]) {
+            assert!(
+                (*count as f64 / total - weight).abs() < 0.03,
+                "{:?}",
+                counts
+            );
+        }
+    }
+
+    #[test]
+    fn is_deterministic_for_a_seed() {
+        let a = mix().seed(7).patch_size(4.0);
+        let b = mix().seed(7).patch_size(4.0);
+        let c = mix().seed(8).patch_size(4.0);
+        assert_eq!(counts(&a), counts(&b));
+        assert_ne!(counts(&a), counts(&c));
+    }
+
+    #[test]
+    fn groups_blocks_into_patches() {
+        let same_as_neighbour = |mix: &BlockMix| {
+            (0..1000)
+                .filter(|&i| mix.pick(i, i % 7, i % 13) == mix.pick(i + 1, i % 7, i % 13))
+                .count()
+        };
+        let noisy = same_as_neighbour(&mix());
+        let patchy = same_as_neighbour(&mix().patch_size(6.0));
+        assert!(patchy > 800, "{}", patchy);
+        assert!(noisy < 600, "{}", noisy);
+    }
+}
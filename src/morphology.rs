@@ -0,0 +1,242 @@
+//! Dilates and erodes the voxel grid, e.g. to thicken one block thin railings that break in survival,
+//! or to close pinholes in scanned meshes before filling them with water.
+
+use crate::error::{Error, Result};
+use crate::voxel_grid::VoxelGrid;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// The neighbourhood of a voxel that an operation looks at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StructuringElement {
+    /// The voxels within `radius` steps along the axes, i.e. the 6 face neighbours for a radius of 1
+    Cross(i32),
+    /// The voxels within `radius` along every axis, i.e. all 26 neighbours for a radius of 1
+    Cube(i32),
+    /// The voxels whose centre is within `radius` of the voxel's centre
+    Sphere(i32),
+}
+
+impl StructuringElement {
+    fn radius(&self) -> i32 {
+        match *self {
+            StructuringElement::Cross(r)
+            | StructuringElement::Cube(r)
+            | StructuringElement::Sphere(r) => r,
+        }
+    }
+
+    /// The offsets of the voxels in the element, including the centre.
+    pub fn offsets(&self) -> Vec<(i32, i32, i32)> {
+        let r = self.radius();
+        let mut offsets = Vec::new();
+        for x in -r..=r {
+            for y in -r..=r {
+                for z in -r..=r {
+                    let inside = match self {
+                        StructuringElement::Cross(_) => x.abs() + y.abs() + z.abs() <= r,
+                        StructuringElement::Cube(_) => true,
+                        StructuringElement::Sphere(_) => x * x + y * y + z * z <= r * r,
+                    };
+                    if inside {
+                        offsets.push((x, y, z));
+                    }
+                }
+            }
+        }
+        offsets
+    }
+}
+
+/// Fill every voxel that the element, centred on a filled voxel, covers. The grid grows where needed so nothing is
+/// cut off. Returns how many voxels were filled.
+pub fn dilate(grid: &mut VoxelGrid, element: &StructuringElement) -> usize {
+    let offsets = element.offsets();
+    let added: HashSet<_> = grid
+        .filled()
+        .flat_map(|(x, y, z)| offsets.iter().map(move |(i, j, k)| (x + i, y + j, z + k)))
+        .filter(|&(x, y, z)| !grid.get(x, y, z))
+        .collect();
+
+    let min = added
+        .iter()
+        .fold((0, 0, 0), |m, p| (m.0.min(p.0), m.1.min(p.1), m.2.min(p.2)));
+    let max = added
+        .iter()
+        .fold((0, 0, 0), |m, p| (m.0.max(p.0), m.1.max(p.1), m.2.max(p.2)));
+    let (sx, sy, sz) = grid.grow_to_fit(min, max);
+    for &(x, y, z) in &added {
+        grid.set(x + sx, y + sy, z + sz, true);
+    }
+    added.len()
+}
+
+/// Empty every filled voxel where the element, centred on it, covers an empty voxel.
+/// Everything outside the grid counts as empty. Returns how many voxels were emptied.
+pub fn erode(grid: &mut VoxelGrid, element: &StructuringElement) -> usize {
+    let offsets = element.offsets();
+    let removed: Vec<_> = grid
+        .filled()
+        .filter(|&(x, y, z)| {
+            offsets.iter().any(|(i, j, k)| {
+                !grid.contains(x + i, y + j, z + k) || !grid.get(x + i, y + j, z + k)
+            })
+        })
+        .collect();
+    for &(x, y, z) in &removed {
+        grid.set(x, y, z, false);
+    }
+    removed.len()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    /// Grow the model by the element
+    Dilate,
+    /// Shrink the model by the element
+    Erode,
+    /// Erode then dilate, which removes features thinner than the element
+    Open,
+    /// Dilate then erode, which fills holes and gaps smaller than the element
+    Close,
+}
+
+/// One post-processing step, parsed from `OPERATION[:SHAPE[:RADIUS]]`, e.g. `close` or `dilate:sphere:2`.
+/// The shape is one of `cross`, `cube` or `sphere` and defaults to a cube with a radius of 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MorphologyStep {
+    pub operation: Operation,
+    pub element: StructuringElement,
+}
+
+impl MorphologyStep {
+    /// Apply the step to the grid. Returns how many voxels were filled and how many were emptied.
+    /// Opening and closing crop the grid back to the model afterwards, as the dilation can leave empty space around it.
+    pub fn apply(&self, grid: &mut VoxelGrid) -> (usize, usize) {
+        match self.operation {
+            Operation::Dilate => (dilate(grid, &self.element), 0),
+            Operation::Erode => (0, erode(grid, &self.element)),
+            Operation::Open => {
+                let emptied = erode(grid, &self.element);
+                let filled = dilate(grid, &self.element);
+                grid.crop_to_filled();
+                (filled, emptied)
+            }
+            Operation::Close => {
+                let filled = dilate(grid, &self.element);
+                let emptied = erode(grid, &self.element);
+                grid.crop_to_filled();
+                (filled, emptied)
+            }
+        }
+    }
+}
+
+impl FromStr for MorphologyStep {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |message: String| Error::invalid_argument("morph", message);
+        let mut fields = s.trim().split(':').map(|f| f.trim().to_lowercase());
+        let operation = match fields.next().as_deref() {
+            Some("dilate") => Operation::Dilate,
+            Some("erode") => Operation::Erode,
+            Some("open") => Operation::Open,
+            Some("close") => Operation::Close,
+            _ => {
+                return Err(invalid(format!(
+                    "{:?} should start with dilate, erode, open or close",
+                    s
+                )))
+            }
+        };
+        let shape = fields.next();
+        let radius = match fields.next() {
+            Some(r) => r
+                .parse()
+                .ok()
+                .filter(|&r: &i32| r >= 1)
+                .ok_or_else(|| invalid(format!("{:?} is not a radius of at least 1", r)))?,
+            None => 1,
+        };
+        let element = match shape.as_deref() {
+            Some("cross") => StructuringElement::Cross(radius),
+            None | Some("cube") => StructuringElement::Cube(radius),
+            Some("sphere") => StructuringElement::Sphere(radius),
+            Some(other) => {
+                return Err(invalid(format!("{:?} is not cross, cube or sphere", other)))
+            }
+        };
+        if fields.next().is_some() {
+            return Err(invalid(format!("{:?} has too many parts", s)));
+        }
+        Ok(Self { operation, element })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn builds_structuring_elements() {
+        assert_eq!(StructuringElement::Cross(1).offsets().len(), 7);
+        assert_eq!(StructuringElement::Cube(1).offsets().len(), 27);
+        assert_eq!(StructuringElement::Sphere(1).offsets().len(), 7);
+        assert_eq!(StructuringElement::Sphere(2).offsets().len(), 33);
+    }
+
+    #[test]
+    fn dilates_beyond_the_grid() {
+        let mut grid = VoxelGrid::from_fn((1, 1, 1), |_, _, _| true);
+        assert_eq!(dilate(&mut grid, &StructuringElement::Cube(1)), 26);
+        assert_eq!(grid.dimensions, (3, 3, 3));
+        assert_eq!(grid.filled().count(), 27);
+
+        assert_eq!(erode(&mut grid, &StructuringElement::Cross(1)), 26);
+        assert!(*grid.get(1, 1, 1));
+    }
+
+    #[test]
+    fn closes_pinholes() {
+        // A plate with a hole in the middle
+        let mut grid = VoxelGrid::from_fn((5, 1, 5), |x, _, z| (x, z) != (2, 2));
+        let step: MorphologyStep = "close".parse().unwrap();
+        step.apply(&mut grid);
+        // The grid is cropped back to the plate, which stays where it was in the model
+        assert_eq!(grid.dimensions, (5, 1, 5));
+        assert_eq!(grid.origin, Vector3::zeros());
+        assert!(*grid.get(2, 0, 2));
+        assert_eq!(grid.filled().count(), 25);
+    }
+
+    #[test]
+    fn opens_away_thin_features() {
+        // A 3x3x3 block with a one voxel thick antenna on top
+        let mut grid = VoxelGrid::from_fn((3, 6, 3), |x, y, z| y < 3 || (x, z) == (1, 1));
+        let step: MorphologyStep = "open:cube:1".parse().unwrap();
+        step.apply(&mut grid);
+        assert_eq!(grid.filled().count(), 27);
+        assert!(!grid.get(1, 4, 1));
+    }
+
+    #[test]
+    fn parses_steps() {
+        assert_eq!(
+            "dilate:sphere:2".parse::<MorphologyStep>().unwrap(),
+            MorphologyStep {
+                operation: Operation::Dilate,
+                element: StructuringElement::Sphere(2),
+            }
+        );
+        assert_eq!(
+            "Erode:cube".parse::<MorphologyStep>().unwrap().element,
+            StructuringElement::Cube(1)
+        );
+        assert!("grow".parse::<MorphologyStep>().is_err());
+        assert!("dilate:star".parse::<MorphologyStep>().is_err());
+        assert!("dilate:cube:0".parse::<MorphologyStep>().is_err());
+        assert!("dilate:cube:1:2".parse::<MorphologyStep>().is_err());
+    }
+}
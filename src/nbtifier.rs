@@ -1,18 +1,43 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
 use std::time::SystemTime;
 
 use nbt::{Blob, Value};
 
-use crate::config::Config;
+use crate::blocks::BlockState;
+use crate::error::{Error, Result};
 use crate::nbt_helper::list_from_intvec;
+use crate::version::MinecraftVersion;
 use crate::voxel_grid::VoxelGrid;
 
+/// Everything an output format needs to know besides the voxel grid itself.
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    /// The Minecraft version to write for
+    pub version: MinecraftVersion,
+    /// The name of the schematic, where the format supports one
+    pub name: String,
+    /// The block state to fill non-empty cells with
+    pub block: BlockState,
+}
+
+impl OutputOptions {
+    pub fn new(version: MinecraftVersion, name: &str, block: BlockState) -> Self {
+        Self {
+            version,
+            name: name.to_string(),
+            block,
+        }
+    }
+}
+
 pub trait NBTIfy {
     /// Convert the voxel grid into a suitable NBT format
     /// # Arguments
     /// * `grid`: The VoxelGrid to use
-    /// * `block`: The Block ID string to fill non-empty cells with
-    fn convert(&self, grid: &VoxelGrid, config: &Config) -> anyhow::Result<Blob>;
+    /// * `options`: The DataVersion, name and block to write
+    fn convert(&self, grid: &VoxelGrid, options: &OutputOptions) -> Result<Blob>;
 
     /// Get the appropriate file extension for this format
     fn file_ending(&self) -> &'static str;
@@ -20,7 +45,7 @@ pub trait NBTIfy {
 
 pub fn varint_from_int(mut i: u32) -> Vec<u8> {
     let mut output = vec![];
-    while (i & 128) != 0 {
+    while i >= 128 {
         output.push((i & 127 | 128) as u8);
         i >>= 7;
     }
@@ -36,6 +61,31 @@ pub fn varint_from_intarray(array: Vec<u32>) -> Vec<u8> {
     output
 }
 
+/// The inverse of `varint_from_intarray`. Returns `None` if the last varint is cut off, or if a varint is longer
+/// than the 5 bytes a `u32` needs.
+pub fn intarray_from_varint(bytes: &[i8]) -> Option<Vec<u32>> {
+    let mut output = vec![];
+    let (mut value, mut shift) = (0u32, 0);
+    for &byte in bytes {
+        let byte = byte as u8;
+        value |= ((byte & 127) as u32) << shift;
+        if byte & 128 != 0 {
+            shift += 7;
+            if shift > 28 {
+                return None;
+            }
+        } else {
+            output.push(value);
+            value = 0;
+            shift = 0;
+        }
+    }
+    match shift {
+        0 => Some(output),
+        _ => None,
+    }
+}
+
 /// Convert a Vec<u8> to Vec<i8> without copying
 /// Borrowed from https://stackoverflow.com/questions/59707349/cast-vector-of-i8-to-vector-of-u8-in-rust
 pub fn bytearray_from_varint(array: Vec<u8>) -> Vec<i8> {
@@ -58,14 +108,24 @@ pub struct SchematicV1;
 pub struct SchematicV2;
 
 impl NBTIfy for SchematicV2 {
-    fn convert(&self, grid: &VoxelGrid, config: &Config) -> anyhow::Result<Blob> {
+    fn convert(&self, grid: &VoxelGrid, options: &OutputOptions) -> Result<Blob> {
+        // The size is stored in shorts, which are read as unsigned
+        let (x, y, z) = grid.dimensions;
+        let max = u16::MAX as i32;
+        if x > max || y > max || z > max {
+            return Err(Error::GridTooLarge { x, y, z, max });
+        }
+
         let mut root = nbt::Blob::new();
 
         root.insert("Version".to_string(), Value::Int(2))?;
-        root.insert("DataVersion".to_string(), Value::Int(config.data_version))?;
+        root.insert(
+            "DataVersion".to_string(),
+            Value::Int(options.version.data_version()),
+        )?;
 
         let mut metadata = HashMap::new();
-        metadata.insert("Name".to_string(), Value::String(config.filename.clone()));
+        metadata.insert("Name".to_string(), Value::String(options.name.clone()));
         metadata.insert(
             "Author".to_string(),
             Value::String("threed2vox".to_string()),
@@ -78,34 +138,36 @@ impl NBTIfy for SchematicV2 {
         metadata.insert("RequiredMods".to_string(), Value::List(Vec::new()));
         root.insert("Metadata".to_string(), Value::Compound(metadata))?;
 
-        let (x, y, z) = grid.dimensions;
         root.insert("Width".to_string(), Value::Short(x as i16))?;
         root.insert("Height".to_string(), Value::Short(y as i16))?;
         root.insert("Length".to_string(), Value::Short(z as i16))?;
 
-        root.insert("PaletteMax".to_string(), Value::Int(2))?;
-
-        // Set the palette
+        // Air is always first, the other blocks are numbered as they're found
         let mut palette = HashMap::new();
-
-        palette.insert("minecraft:air".to_string(), Value::Int(0));
-        palette.insert(config.block.clone(), Value::Int(1));
-
-        root.insert("Palette".to_string(), Value::Compound(palette))?;
-
-        // Insert block data
+        palette.insert(BlockState::air(), 0);
         let mut block_data: Vec<u32> = Vec::new();
         for y in 0..grid.dimensions.1 {
             for z in 0..grid.dimensions.2 {
                 for x in 0..grid.dimensions.0 {
-                    let id = match grid.get(x, y, z) {
-                        true => 1,
-                        false => 0,
+                    let id = match block_at(grid, options, x, y, z) {
+                        Some(block) => {
+                            let next = palette.len() as u32;
+                            *palette.entry(block.clone()).or_insert(next)
+                        }
+                        None => 0,
                     };
                     block_data.push(id);
                 }
             }
         }
+
+        root.insert("PaletteMax".to_string(), Value::Int(palette.len() as i32))?;
+        let palette = palette
+            .into_iter()
+            .map(|(block, id)| (block.to_string(), Value::Int(id as i32)))
+            .collect();
+        root.insert("Palette".to_string(), Value::Compound(palette))?;
+
         let block_data = varint_from_intarray(block_data);
         root.insert(
             "BlockData".to_string(),
@@ -125,27 +187,30 @@ impl NBTIfy for SchematicV2 {
 pub struct StructureFormat;
 
 impl NBTIfy for StructureFormat {
-    fn convert(&self, grid: &VoxelGrid, config: &Config) -> anyhow::Result<Blob> {
+    fn convert(&self, grid: &VoxelGrid, options: &OutputOptions) -> Result<Blob> {
         let mut root = nbt::Blob::new();
-        root.insert("DataVersion".to_string(), Value::Int(config.data_version))?;
+        root.insert(
+            "DataVersion".to_string(),
+            Value::Int(options.version.data_version()),
+        )?;
         let (x, y, z) = grid.dimensions;
         root.insert("size", list_from_intvec(vec![x, y, z]))?;
 
         // Unlike schematics, we can get away with only having non-air blocks in an nbt
-        let palette = vec![Value::Compound(maplit::hashmap! {
-            "Name".to_string() => Value::String(config.block.clone()),
-            "Properties".to_string() => Value::Compound(HashMap::new())
-        })];
-        root.insert("palette".to_string(), Value::List(palette))?;
-
+        let mut palette: Vec<&BlockState> = Vec::new();
+        let mut ids = HashMap::new();
         let mut block_data: Vec<Value> = Vec::new();
         for y in 0..grid.dimensions.1 {
             for z in 0..grid.dimensions.2 {
                 for x in 0..grid.dimensions.0 {
-                    if *grid.get(x, y, z) {
+                    if let Some(block) = block_at(grid, options, x, y, z) {
+                        let id = *ids.entry(block).or_insert_with(|| {
+                            palette.push(block);
+                            palette.len() as i32 - 1
+                        });
                         let pos = list_from_intvec(vec![x, y, z]);
                         let value = Value::Compound(maplit::hashmap! {
-                            "state".to_string() => Value::Int(0),
+                            "state".to_string() => Value::Int(id),
                             "pos".to_string() => pos
                         });
                         block_data.push(value);
@@ -153,6 +218,8 @@ impl NBTIfy for StructureFormat {
                 }
             }
         }
+        let palette = palette.into_iter().map(palette_entry).collect();
+        root.insert("palette".to_string(), Value::List(palette))?;
         root.insert("blocks".to_string(), Value::List(block_data))?;
 
         Ok(root)
@@ -162,3 +229,328 @@ impl NBTIfy for StructureFormat {
         "nbt"
     }
 }
+
+/// The block to write in a voxel, or `None` if it's empty.
+pub(crate) fn block_at<'a>(
+    grid: &'a VoxelGrid,
+    options: &'a OutputOptions,
+    x: i32,
+    y: i32,
+    z: i32,
+) -> Option<&'a BlockState> {
+    match grid.get(x, y, z) {
+        true => Some(grid.block(x, y, z).unwrap_or(&options.block)),
+        false => None,
+    }
+}
+
+/// A structure palette entry, with a `Properties` compound if the block state has any.
+fn palette_entry(block: &BlockState) -> Value {
+    let mut entry = maplit::hashmap! {
+        "Name".to_string() => Value::String(block.name.clone())
+    };
+    if !block.properties.is_empty() {
+        let properties = block
+            .properties
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+        entry.insert("Properties".to_string(), Value::Compound(properties));
+    }
+    Value::Compound(entry)
+}
+
+/// Read a schematic (`.schem`) or structure (`.nbt`) file back into a voxel grid, with every block set explicitly,
+/// e.g. to look at a schematic written earlier or by another tool.
+pub fn read_schematic(path: &str) -> Result<VoxelGrid> {
+    let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let root = Blob::from_gzip_reader(&mut file)?;
+    let extension = Path::new(path).extension().unwrap_or_default();
+    match extension.to_string_lossy().to_lowercase().as_str() {
+        "schem" => read_schematic_v2(path, &root),
+        "nbt" => read_structure(path, &root),
+        other => Err(Error::UnsupportedFormat(other.to_string())),
+    }
+}
+
+fn read_schematic_v2(path: &str, root: &Blob) -> Result<VoxelGrid> {
+    let size = |key: &'static str| match root.get(key) {
+        Some(Value::Short(n)) => Ok(*n as u16 as i32),
+        _ => Err(Error::parse(
+            path,
+            format!("Missing the schematic's {}", key),
+        )),
+    };
+    let (dx, dy, dz) = (size("Width")?, size("Height")?, size("Length")?);
+    check_size(path, dx, dy, dz)?;
+    let palette = match root.get("Palette") {
+        Some(Value::Compound(palette)) => palette,
+        _ => return Err(Error::parse(path, "Missing the schematic's palette")),
+    };
+    let mut blocks = HashMap::new();
+    for (block, id) in palette {
+        let id = match id {
+            Value::Int(id) => *id as u32,
+            _ => return Err(Error::parse(path, "Palette IDs should be integers")),
+        };
+        blocks.insert(id, block.parse::<BlockState>()?);
+    }
+    let data = match root.get("BlockData") {
+        Some(Value::ByteArray(data)) => intarray_from_varint(data).ok_or_else(|| {
+            Error::parse(
+                path,
+                "The block data ends in the middle of a block or has a block ID that's too long",
+            )
+        })?,
+        _ => return Err(Error::parse(path, "Missing the schematic's block data")),
+    };
+    // Up to 65535 blocks along each axis, which doesn't fit in an i32
+    let volume = dx as i64 * dy as i64 * dz as i64;
+    if data.len() as i64 != volume {
+        return Err(Error::parse(
+            path,
+            format!(
+                "Expected {} blocks for a {}x{}x{} schematic, found {}",
+                volume,
+                dx,
+                dy,
+                dz,
+                data.len()
+            ),
+        ));
+    }
+
+    let mut grid = VoxelGrid::new(dx, dy, dz);
+    // Blocks are ordered by Y, then Z, then X
+    for (i, id) in data.into_iter().enumerate() {
+        let i = i as i32;
+        let (x, y, z) = (i % dx, i / (dx * dz), i / dx % dz);
+        let block = blocks
+            .get(&id)
+            .ok_or_else(|| Error::parse(path, format!("Block ID {} isn't in the palette", id)))?;
+        if block.name != "minecraft:air" {
+            grid.set_block(x, y, z, block.clone());
+        }
+    }
+    Ok(grid)
+}
+
+/// Fail unless the size read from a file has at least one block along each axis.
+fn check_size(path: &str, x: i32, y: i32, z: i32) -> Result<()> {
+    if x < 1 || y < 1 || z < 1 {
+        return Err(Error::parse(
+            path,
+            format!("The size {}x{}x{} has no blocks in it", x, y, z),
+        ));
+    }
+    Ok(())
+}
+
+fn read_structure(path: &str, root: &Blob) -> Result<VoxelGrid> {
+    let ints = |value: Option<&Value>| match value {
+        Some(Value::List(values)) if values.len() == 3 => values
+            .iter()
+            .map(|v| match v {
+                Value::Int(n) => Some(*n),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>(),
+        _ => None,
+    };
+    let size =
+        ints(root.get("size")).ok_or_else(|| Error::parse(path, "Missing the structure's size"))?;
+    check_size(path, size[0], size[1], size[2])?;
+    let palette = match root.get("palette") {
+        Some(Value::List(palette)) => palette
+            .iter()
+            .map(|entry| block_from_palette_entry(path, entry))
+            .collect::<Result<Vec<_>>>()?,
+        _ => return Err(Error::parse(path, "Missing the structure's palette")),
+    };
+    let blocks = match root.get("blocks") {
+        Some(Value::List(blocks)) => blocks,
+        _ => return Err(Error::parse(path, "Missing the structure's blocks")),
+    };
+
+    let mut grid = VoxelGrid::new(size[0], size[1], size[2]);
+    for entry in blocks {
+        let entry = match entry {
+            Value::Compound(entry) => entry,
+            _ => return Err(Error::parse(path, "Blocks should be compounds")),
+        };
+        let pos = ints(entry.get("pos"))
+            .ok_or_else(|| Error::parse(path, "A block is missing its position"))?;
+        let block = match entry.get("state") {
+            Some(Value::Int(id)) => palette.get(*id as usize),
+            _ => None,
+        }
+        .ok_or_else(|| Error::parse(path, "A block's state isn't in the palette"))?;
+        if block.name != "minecraft:air" && grid.contains(pos[0], pos[1], pos[2]) {
+            grid.set_block(pos[0], pos[1], pos[2], block.clone());
+        }
+    }
+    Ok(grid)
+}
+
+/// The inverse of `palette_entry`.
+fn block_from_palette_entry(path: &str, entry: &Value) -> Result<BlockState> {
+    let entry = match entry {
+        Value::Compound(entry) => entry,
+        _ => return Err(Error::parse(path, "Palette entries should be compounds")),
+    };
+    let mut block = match entry.get("Name") {
+        Some(Value::String(name)) => BlockState::new(name),
+        _ => return Err(Error::parse(path, "A palette entry is missing its name")),
+    };
+    if let Some(Value::Compound(properties)) = entry.get("Properties") {
+        for (key, value) in properties {
+            if let Value::String(value) = value {
+                block = block.with(key, value);
+            }
+        }
+    }
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_varints() {
+        assert_eq!(varint_from_int(1), vec![1]);
+        assert_eq!(varint_from_int(127), vec![127]);
+        assert_eq!(varint_from_int(128), vec![128, 1]);
+        // Bit 7 of 300 is clear, but it still needs a second byte
+        assert_eq!(varint_from_int(300), vec![172, 2]);
+        assert_eq!(varint_from_int(u32::MAX), vec![255, 255, 255, 255, 15]);
+    }
+
+    fn options() -> OutputOptions {
+        OutputOptions::new(
+            MinecraftVersion::from_data_version(2566),
+            "test",
+            BlockState::new("oak_log").with("axis", "x"),
+        )
+    }
+
+    fn grid() -> VoxelGrid {
+        let mut grid = VoxelGrid::new(3, 1, 1);
+        grid.set(1, 0, 0, true);
+        grid
+    }
+
+    #[test]
+    fn rejects_malformed_schematics() {
+        // Six bytes for one block ID
+        assert_eq!(intarray_from_varint(&[-1, -1, -1, -1, -1, 1]), None);
+        assert_eq!(
+            intarray_from_varint(&[-1, -1, -1, -1, 15]),
+            Some(vec![u32::MAX])
+        );
+
+        let schematic = |size: i16| {
+            let mut root = Blob::new();
+            for key in &["Width", "Height", "Length"] {
+                root.insert(key.to_string(), Value::Short(size)).unwrap();
+            }
+            let palette = HashMap::from([("minecraft:air".to_string(), Value::Int(0))]);
+            root.insert("Palette".to_string(), Value::Compound(palette))
+                .unwrap();
+            root.insert("BlockData".to_string(), Value::ByteArray(vec![0]))
+                .unwrap();
+            read_schematic_v2("test.schem", &root)
+        };
+        assert!(schematic(1).is_ok());
+        assert!(schematic(0).is_err());
+        // 65535 blocks along each axis
+        assert!(schematic(-1).is_err());
+
+        let mut root = Blob::new();
+        root.insert("size", list_from_intvec(vec![2, 0, 2]))
+            .unwrap();
+        root.insert("palette", Value::List(Vec::new())).unwrap();
+        root.insert("blocks", Value::List(Vec::new())).unwrap();
+        assert!(read_structure("test.nbt", &root).is_err());
+    }
+
+    #[test]
+    fn writes_block_states_in_schematic_palettes() {
+        let blob = SchematicV2.convert(&grid(), &options()).unwrap();
+        match &blob["Palette"] {
+            Value::Compound(palette) => {
+                assert_eq!(palette["minecraft:oak_log[axis=x]"], Value::Int(1));
+                assert_eq!(palette["minecraft:air"], Value::Int(0));
+            }
+            other => panic!("Expected a compound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_block_states_in_structure_palettes() {
+        let blob = StructureFormat.convert(&grid(), &options()).unwrap();
+        let expected = Value::Compound(maplit::hashmap! {
+            "Name".to_string() => Value::String("minecraft:oak_log".to_string()),
+            "Properties".to_string() => Value::Compound(maplit::hashmap! {
+                "axis".to_string() => Value::String("x".to_string())
+            })
+        });
+        assert_eq!(blob["palette"], Value::List(vec![expected]));
+    }
+
+    #[test]
+    fn rejects_schematics_too_large_to_store() {
+        // e.g. two models far apart, combined into one grid
+        let mut grid = grid();
+        grid.grow_to_fit((0, 0, 0), (70_000, 0, 0));
+        let result = SchematicV2.convert(&grid, &options());
+        assert!(matches!(result, Err(Error::GridTooLarge { x: 70_001, .. })));
+    }
+
+    #[test]
+    fn writes_blocks_set_per_voxel() {
+        let mut grid = grid();
+        grid.set_block(2, 0, 0, BlockState::new("glass"));
+        let blob = SchematicV2.convert(&grid, &options()).unwrap();
+        assert_eq!(blob["PaletteMax"], Value::Int(3));
+        assert_eq!(blob["BlockData"], Value::ByteArray(vec![0, 1, 2]));
+
+        let blob = StructureFormat.convert(&grid, &options()).unwrap();
+        match &blob["palette"] {
+            Value::List(palette) => assert_eq!(palette.len(), 2),
+            other => panic!("Expected a list, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let mut grid = grid();
+        grid.set_block(2, 0, 0, BlockState::new("glass"));
+        let dir = std::env::temp_dir();
+        for (format, extension) in [
+            (&SchematicV2 as &dyn NBTIfy, "schem"),
+            (&StructureFormat, "nbt"),
+        ] {
+            let path = dir.join(format!(
+                "threed2vox-read-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            let path = path.to_str().unwrap();
+            let blob = format.convert(&grid, &options()).unwrap();
+            blob.to_gzip_writer(&mut File::create(path).unwrap())
+                .unwrap();
+
+            let read = read_schematic(path).unwrap();
+            assert_eq!(read.dimensions, (3, 1, 1));
+            assert!(!read.get(0, 0, 0));
+            assert_eq!(read.block(1, 0, 0), Some(&options().block));
+            assert_eq!(read.block(2, 0, 0), Some(&BlockState::new("glass")));
+            std::fs::remove_file(path).unwrap();
+        }
+        assert_eq!(
+            intarray_from_varint(&bytearray_from_varint(varint_from_intarray(vec![1, 300]))),
+            Some(vec![1, 300])
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Turns directional blocks, like logs and glazed terracotta, to follow the surface of the model.
+
+use crate::blocks::{BlockRegistry, BlockState};
+use crate::voxel_grid::VoxelGrid;
+use nalgebra::Vector3;
+
+/// The names Minecraft uses for the negative and positive direction along each axis.
+const DIRECTIONS: [(&str, &str); 3] = [("west", "east"), ("down", "up"), ("north", "south")];
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+
+/// The axis with the largest component of `normal`, and whether that component is positive.
+/// With `horizontal`, the Y axis is ignored.
+fn dominant_axis(normal: &Vector3<f32>, horizontal: bool) -> Option<(usize, bool)> {
+    let axes: &[usize] = if horizontal { &[0, 2] } else { &[0, 1, 2] };
+    axes.iter()
+        .map(|&axis| (axis, normal[axis]))
+        .filter(|(_, component)| component.abs() > 1e-3)
+        .max_by(|a, b| a.1.abs().partial_cmp(&b.1.abs()).unwrap())
+        .map(|(axis, component)| (axis, component > 0.0))
+}
+
+/// Set the `axis` and `facing` of a block from a surface normal, if it has those properties and they aren't set yet.
+/// Logs and pillars get the axis closest to the normal, so their end faces the outside. Blocks with a
+/// `facing` face outwards, e.g. an observer's face or the top of a glazed terracotta pattern.
+fn orient(
+    block: &BlockState,
+    normal: &Vector3<f32>,
+    registry: &BlockRegistry,
+) -> Option<BlockState> {
+    let properties = &registry.get(&block.name)?.properties;
+    let mut oriented = block.clone();
+
+    if let (Some(values), None) = (properties.get("axis"), block.get("axis")) {
+        if let Some((axis, _)) = dominant_axis(normal, false) {
+            if values.iter().any(|v| v == AXES[axis]) {
+                oriented = oriented.with("axis", AXES[axis]);
+            }
+        }
+    }
+    if let (Some(values), None) = (properties.get("facing"), block.get("facing")) {
+        let horizontal = !values.iter().any(|v| v == "up");
+        if let Some((axis, positive)) = dominant_axis(normal, horizontal) {
+            let (negative_name, positive_name) = DIRECTIONS[axis];
+            let facing = if positive {
+                positive_name
+            } else {
+                negative_name
+            };
+            if values.iter().any(|v| v == facing) {
+                oriented = oriented.with("facing", facing);
+            }
+        }
+    }
+
+    Some(oriented).filter(|oriented| oriented != block)
+}
+
+/// Orient the block in every voxel the surface passes through, using `default` where the grid doesn't set a block.
+/// Returns how many voxels were changed.
+pub fn orient_blocks(
+    grid: &mut VoxelGrid,
+    default: &BlockState,
+    registry: &BlockRegistry,
+) -> usize {
+    let changes: Vec<_> = grid
+        .filled()
+        .filter_map(|(x, y, z)| {
+            let normal = grid.normal(x, y, z)?;
+            let block = grid.block(x, y, z).unwrap_or(default);
+            orient(block, normal, registry).map(|block| ((x, y, z), block))
+        })
+        .collect();
+
+    let count = changes.len();
+    for ((x, y, z), block) in changes {
+        grid.set_block(x, y, z, block);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_grid::Surface;
+
+    #[test]
+    fn orients_logs_along_the_normal() {
+        let registry = BlockRegistry::embedded();
+        let log = BlockState::new("oak_log");
+        let normal = Vector3::new(0.2, -0.9, 0.1);
+        assert_eq!(
+            orient(&log, &normal, &registry),
+            Some(log.clone().with("axis", "y"))
+        );
+        // Properties that are already set are kept
+        let fixed = log.with("axis", "x");
+        assert_eq!(orient(&fixed, &normal, &registry), None);
+    }
+
+    #[test]
+    fn faces_blocks_outwards() {
+        let registry = BlockRegistry::embedded();
+        let normal = Vector3::new(0.1, 0.8, -0.5);
+        let observer = BlockState::new("observer");
+        assert_eq!(
+            orient(&observer, &normal, &registry).unwrap().get("facing"),
+            Some("up")
+        );
+        // Glazed terracotta can only face horizontally
+        let terracotta = BlockState::new("red_glazed_terracotta");
+        assert_eq!(
+            orient(&terracotta, &normal, &registry)
+                .unwrap()
+                .get("facing"),
+            Some("north")
+        );
+        assert_eq!(orient(&terracotta, &Vector3::y(), &registry), None);
+    }
+
+    #[test]
+    fn leaves_other_blocks_alone() {
+        let registry = BlockRegistry::embedded();
+        let mut grid = VoxelGrid::new(1, 1, 1);
+        grid.set(0, 0, 0, true);
+        grid.set_surface(
+            0,
+            0,
+            0,
+            Surface {
+                normal: Vector3::x(),
+                offset: 0.0,
+            },
+        );
+        assert_eq!(
+            orient_blocks(&mut grid, &BlockState::new("stone"), &registry),
+            0
+        );
+        assert_eq!(
+            orient_blocks(&mut grid, &BlockState::new("oak_log"), &registry),
+            1
+        );
+        assert_eq!(
+            grid.block(0, 0, 0),
+            Some(&BlockState::new("oak_log").with("axis", "x"))
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Renders a preview of the output in software, as an isometric view from the south east or as an orthographic
+//! view from the top, front or side, e.g. to check the model's orientation and scale before pasting it.
+
+use crate::blocks::BlockState;
+use crate::error::{Error, Result};
+use crate::nbtifier::{block_at, OutputOptions};
+use crate::raster::{Image, Rgb, BLACK, WHITE};
+use crate::voxel_grid::VoxelGrid;
+use std::str::FromStr;
+
+/// Roughly the colour of blocks whose ID contains each word, checked in order so that e.g. `red_sandstone` is
+/// matched before `sand` and `red`.
+const COLOURS: [(&str, Rgb); 70] = [
+    ("water", [63, 118, 228]),
+    ("lava", [207, 92, 15]),
+    ("glass", [200, 228, 236]),
+    ("ice", [145, 183, 253]),
+    ("snow", [249, 254, 254]),
+    ("red_sand", [190, 102, 33]),
+    ("red_nether", [69, 7, 9]),
+    ("sand", [219, 207, 163]),
+    ("gravel", [131, 127, 126]),
+    ("deepslate", [80, 80, 82]),
+    ("blackstone", [42, 36, 41]),
+    ("basalt", [80, 81, 86]),
+    ("andesite", [136, 136, 136]),
+    ("diorite", [188, 188, 188]),
+    ("granite", [149, 103, 85]),
+    ("tuff", [108, 109, 102]),
+    ("end_stone", [219, 222, 158]),
+    ("obsidian", [15, 10, 24]),
+    ("quartz", [235, 229, 222]),
+    ("prismarine", [99, 156, 151]),
+    ("purpur", [169, 125, 169]),
+    ("nether_brick", [44, 21, 26]),
+    ("netherrack", [97, 38, 38]),
+    ("mud", [60, 57, 61]),
+    ("terracotta", [152, 94, 67]),
+    ("brick", [150, 97, 83]),
+    ("copper", [192, 107, 79]),
+    ("gold", [246, 208, 61]),
+    ("iron", [220, 220, 220]),
+    ("diamond", [98, 237, 228]),
+    ("emerald", [42, 203, 87]),
+    ("lapis", [30, 67, 140]),
+    ("redstone", [175, 24, 5]),
+    ("leaves", [60, 120, 40]),
+    ("grass", [95, 159, 53]),
+    ("moss", [89, 109, 45]),
+    ("dirt", [134, 96, 67]),
+    ("clay", [160, 166, 179]),
+    ("hay", [166, 136, 38]),
+    ("bone", [229, 225, 207]),
+    ("resin", [217, 99, 25]),
+    ("pale_oak", [220, 210, 205]),
+    ("dark_oak", [66, 43, 20]),
+    ("spruce", [114, 84, 48]),
+    ("birch", [192, 175, 121]),
+    ("jungle", [160, 115, 80]),
+    ("acacia", [168, 90, 50]),
+    ("mangrove", [117, 54, 48]),
+    ("cherry", [226, 178, 172]),
+    ("bamboo", [193, 173, 80]),
+    ("crimson", [101, 48, 70]),
+    ("warped", [43, 104, 99]),
+    ("oak", [162, 130, 78]),
+    ("light_blue", [58, 175, 217]),
+    ("light_gray", [142, 142, 134]),
+    ("white", [233, 236, 236]),
+    ("orange", [240, 118, 19]),
+    ("magenta", [189, 68, 179]),
+    ("yellow", [248, 197, 39]),
+    ("lime", [112, 185, 25]),
+    ("pink", [237, 141, 172]),
+    ("gray", [62, 68, 71]),
+    ("cyan", [21, 137, 145]),
+    ("purple", [121, 42, 172]),
+    ("blue", [53, 57, 157]),
+    ("brown", [114, 71, 40]),
+    ("green", [84, 109, 27]),
+    ("red", [161, 39, 34]),
+    ("black", [20, 21, 25]),
+    ("stone", [125, 125, 125]),
+];
+
+/// The colour to draw a block with, or grey for blocks that aren't in the table.
+pub fn block_colour(block: &BlockState) -> Rgb {
+    let id = block.name.rsplit(':').next().unwrap_or_default();
+    COLOURS
+        .iter()
+        .find(|(word, _)| id.contains(word))
+        .map_or([125, 125, 125], |(_, colour)| *colour)
+}
+
+/// How opaque a block is drawn, so glass and water show what's behind them.
+fn opacity(block: &BlockState) -> f32 {
+    if block.name.contains("glass") || block.name.ends_with(":water") {
+        0.5
+    } else {
+        1.0
+    }
+}
+
+fn shade(colour: Rgb, factor: f32) -> Rgb {
+    colour.map(|c| (c as f32 * factor).round().min(255.0) as u8)
+}
+
+/// Which way the preview looks at the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum View {
+    /// From above the south east corner, showing the top, south and east faces
+    Isometric,
+    /// Straight down, with north at the top
+    Top,
+    /// From the south, looking north
+    Front,
+    /// From the east, looking west
+    Side,
+}
+
+impl FromStr for View {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "iso" | "isometric" => Ok(View::Isometric),
+            "top" => Ok(View::Top),
+            "front" => Ok(View::Front),
+            "side" => Ok(View::Side),
+            other => Err(Error::invalid_argument(
+                "preview-view",
+                format!("{:?} should be iso, top, front or side", other),
+            )),
+        }
+    }
+}
+
+/// The faces of a block that an isometric view shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Face {
+    Top,
+    /// The +Z face, on the left of the block
+    South,
+    /// The +X face, on the right of the block
+    East,
+}
+
+/// Which face each pixel of a block's sprite shows, for a block `2 * half` pixels wide and tall, and whether the
+/// pixel is on the edge of its face.
+fn sprite(half: i64) -> Vec<Option<(Face, bool)>> {
+    let size = 2 * half;
+    let face = |u: i64, v: i64| -> Option<Face> {
+        if u < 0 || v < 0 || u >= size || v >= size {
+            return None;
+        }
+        // The pixel's centre, across from the middle of the sprite and down from its top
+        let (across, down, s) = ((u - half) as f64 + 0.5, v as f64 + 0.5, half as f64);
+        let bottom_of_top = s - across.abs() / 2.0;
+        if across.abs() / s + (down - s / 2.0).abs() / (s / 2.0) <= 1.0 {
+            Some(Face::Top)
+        } else if down >= bottom_of_top && down <= bottom_of_top + s {
+            Some(if across < 0.0 {
+                Face::South
+            } else {
+                Face::East
+            })
+        } else {
+            None
+        }
+    };
+    let mut pixels = Vec::with_capacity((size * size) as usize);
+    for v in 0..size {
+        for u in 0..size {
+            pixels.push(face(u, v).map(|f| {
+                let edge = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .iter()
+                    .any(|(du, dv)| face(u + du, v + dv) != Some(f));
+                (f, edge)
+            }));
+        }
+    }
+    pixels
+}
+
+fn draw_caption(image: &mut Image, text: &str) {
+    let y = image.height as i64 - 2 - Image::text_height(1) as i64;
+    image.draw_text(2, y, text, 1, BLACK);
+}
+
+/// Render the grid as an isometric view from above its south east corner, with each block `2 * half` pixels wide.
+fn isometric(grid: &VoxelGrid, options: &OutputOptions, half: i64) -> Image {
+    let (dx, dy, dz) = grid.dimensions;
+    let margin = 4 * half;
+    let width = (dx + dz) as i64 * half + 2 * margin;
+    let height = (dx + dz) as i64 * half / 2 + dy as i64 * half + 2 * margin;
+    let mut image = Image::new(width as u32, height as u32, WHITE);
+    // The top left of the sprite of the block at (0, dy - 1, 0)
+    let (left, top) = (margin + dz as i64 * half - half, margin);
+
+    let sprite = sprite(half);
+    let is_filled =
+        |x, y, z| *grid.get(x, y, z) && opacity(block_at(grid, options, x, y, z).unwrap()) == 1.0;
+    let mut visible: Vec<_> = grid
+        .filled()
+        .filter(|&(x, y, z)| {
+            !(is_filled(x + 1, y, z) && is_filled(x, y + 1, z) && is_filled(x, y, z + 1))
+        })
+        .collect();
+    // Back to front, since blocks with a larger X + Y + Z are nearer the viewer
+    visible.sort_unstable_by_key(|&(x, y, z)| (x + y + z, y, x));
+
+    for (x, y, z) in visible {
+        let block = block_at(grid, options, x, y, z).unwrap();
+        let colour = block_colour(block);
+        let alpha = opacity(block);
+        let sx = left + (x - z) as i64 * half;
+        let sy = top + (x + z) as i64 * half / 2 + (dy - 1 - y) as i64 * half;
+        for (i, pixel) in sprite.iter().enumerate() {
+            if let Some((face, edge)) = pixel {
+                let factor = match face {
+                    Face::Top => 1.0,
+                    Face::South => 0.8,
+                    Face::East => 0.62,
+                } * if *edge && half >= 3 { 0.85 } else { 1.0 };
+                let (u, v) = (i as i64 % (2 * half), i as i64 / (2 * half));
+                image.blend(sx + u, sy + v, shade(colour, factor), alpha);
+            }
+        }
+    }
+    draw_caption(
+        &mut image,
+        &format!("{}x{}x{} from the south east", dx, dy, dz),
+    );
+    image
+}
+
+/// Render the grid looking along one axis, shading each block by how far back it is.
+fn orthographic(grid: &VoxelGrid, options: &OutputOptions, view: View, size: i64) -> Image {
+    let (dx, dy, dz) = grid.dimensions;
+    // The image's columns and rows, and the depth from the viewer, as grid coordinates
+    let (columns, rows, depth, caption) = match view {
+        View::Top => (dx, dz, dy, "from above, north is up"),
+        View::Front => (dx, dy, dz, "from the south"),
+        _ => (dz, dy, dx, "from the east"),
+    };
+    let position = |column: i32, row: i32, d: i32| match view {
+        View::Top => (column, dy - 1 - d, row),
+        View::Front => (column, dy - 1 - row, dz - 1 - d),
+        _ => (dx - 1 - d, dy - 1 - row, dz - 1 - column),
+    };
+    let margin = 2 * size;
+    let mut image = Image::new(
+        (columns as i64 * size + 2 * margin) as u32,
+        (rows as i64 * size + 2 * margin + 8) as u32,
+        WHITE,
+    );
+    for row in 0..rows {
+        for column in 0..columns {
+            // Front to back, blending through glass and water until something opaque
+            let mut layers = Vec::new();
+            for d in 0..depth {
+                let (x, y, z) = position(column, row, d);
+                if let Some(block) = block_at(grid, options, x, y, z) {
+                    let factor = 1.0 - 0.5 * d as f32 / depth.max(1) as f32;
+                    layers.push((shade(block_colour(block), factor), opacity(block)));
+                    if opacity(block) == 1.0 {
+                        break;
+                    }
+                }
+            }
+            for (colour, alpha) in layers.into_iter().rev() {
+                for v in 0..size {
+                    for u in 0..size {
+                        let edge = (u == 0 || v == 0) && size >= 6;
+                        image.blend(
+                            margin + column as i64 * size + u,
+                            margin + row as i64 * size + v,
+                            if edge { shade(colour, 0.85) } else { colour },
+                            alpha,
+                        );
+                    }
+                }
+            }
+        }
+    }
+    draw_caption(&mut image, &format!("{}x{}x{} {}", dx, dy, dz, caption));
+    image
+}
+
+/// Render a preview of the output, sized so the image is at most about `max_size` pixels across.
+pub fn render(grid: &VoxelGrid, options: &OutputOptions, view: View, max_size: u32) -> Image {
+    let (dx, dy, dz) = grid.dimensions;
+    match view {
+        View::Isometric => {
+            let across = (dx + dz).max(dy * 2).max(1) as i64;
+            // Even, so the half height of a block is a whole number of pixels
+            let half = (max_size as i64 / across).clamp(2, 16) / 2 * 2;
+            isometric(grid, options, half)
+        }
+        _ => {
+            let across = dx.max(dy).max(dz).max(1) as i64;
+            orthographic(grid, options, view, (max_size as i64 / across).clamp(1, 32))
+        }
+    }
+}
+
+/// Render a preview of the output and write it as a PNG.
+pub fn write_preview(
+    grid: &VoxelGrid,
+    options: &OutputOptions,
+    view: View,
+    path: &str,
+) -> Result<()> {
+    render(grid, options, view, 1024).write_png(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::MinecraftVersion;
+
+    fn options() -> OutputOptions {
+        OutputOptions::new(
+            MinecraftVersion::from_data_version(2566),
+            "test",
+            BlockState::new("stone"),
+        )
+    }
+
+    #[test]
+    fn colours_blocks_by_material() {
+        assert_eq!(
+            block_colour(&BlockState::new("red_sandstone")),
+            [190, 102, 33]
+        );
+        assert_eq!(
+            block_colour(&BlockState::new("light_blue_wool")),
+            [58, 175, 217]
+        );
+        assert_eq!(
+            block_colour(&BlockState::new("dark_oak_planks")),
+            [66, 43, 20]
+        );
+        assert_eq!(
+            block_colour(&BlockState::new("mymod:widget")),
+            [125, 125, 125]
+        );
+    }
+
+    #[test]
+    fn builds_block_sprites() {
+        let sprite = sprite(4);
+        let count = |face| sprite.iter().flatten().filter(|(f, _)| *f == face).count();
+        // The top is a diamond half as tall as it is wide, and the sides split the rest between them
+        assert_eq!(count(Face::Top), 16);
+        assert_eq!(count(Face::South), 16);
+        assert_eq!(count(Face::East), 16);
+        // The top corner and the bottom corner are in the middle of the sprite
+        assert_eq!(sprite[3].map(|(f, _)| f), Some(Face::Top));
+        assert_eq!(sprite[7 * 8 + 3].map(|(f, _)| f), Some(Face::South));
+        assert_eq!(sprite[7 * 8 + 4].map(|(f, _)| f), Some(Face::East));
+        assert_eq!(sprite[0], None);
+    }
+
+    #[test]
+    fn renders_views() {
+        let mut grid = VoxelGrid::new(2, 2, 2);
+        grid.set(0, 0, 0, true);
+        grid.set_block(1, 1, 1, BlockState::new("gold_block"));
+
+        let top = render(&grid, &options(), View::Top, 64);
+        let size = 32;
+        let centre = |image: &Image, column: u32, row: u32| {
+            image.get(
+                2 * size + column * size + size / 2,
+                2 * size + row * size + size / 2,
+            )
+        };
+        // Gold is on the top layer, stone is lower down and darker
+        assert_eq!(centre(&top, 1, 1), block_colour(&BlockState::new("gold")));
+        assert_eq!(centre(&top, 0, 0), shade([125, 125, 125], 0.75));
+        assert_eq!(centre(&top, 1, 0), WHITE);
+
+        // From the front, the gold block is in the top right
+        let front = render(&grid, &options(), View::Front, 64);
+        assert_eq!(centre(&front, 1, 0), block_colour(&BlockState::new("gold")));
+        assert_eq!(centre(&front, 0, 1), shade([125, 125, 125], 0.75));
+
+        let iso = render(&grid, &options(), View::Isometric, 64);
+        assert!(iso.width > 0);
+        assert!("ISO".parse::<View>().is_ok());
+        assert!("back".parse::<View>().is_err());
+    }
+}
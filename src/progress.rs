@@ -0,0 +1,126 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A snapshot of how far along a conversion is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// The name of the current stage, e.g. "Voxelising"
+    pub stage: &'static str,
+    /// How much of the current stage is done, from 0 to 1
+    pub fraction: f32,
+}
+
+/// Called with progress updates, in order and one at a time, from whichever thread finished a step.
+/// Other threads wait for the callback to return before reporting their own steps, so keep it quick.
+/// To receive updates over a channel instead, send them from the callback.
+pub type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Lets a long-running conversion be aborted from another thread.
+/// Clones share the same flag, so keep one and hand a clone to the voxelizer.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Ask the conversion to stop at the next opportunity.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Counts finished steps of a stage, forwarding progress to the log and the callback.
+/// Steps may finish on several threads, so counting and reporting happen under one lock to keep reports in order.
+pub(crate) struct Reporter<'a> {
+    stage: &'static str,
+    total: usize,
+    done: Mutex<usize>,
+    callback: Option<&'a ProgressCallback>,
+    cancellation: Option<&'a CancellationToken>,
+}
+
+impl<'a> Reporter<'a> {
+    pub(crate) fn new(
+        stage: &'static str,
+        total: usize,
+        callback: Option<&'a ProgressCallback>,
+        cancellation: Option<&'a CancellationToken>,
+    ) -> Self {
+        let reporter = Self {
+            stage,
+            total,
+            done: Mutex::new(0),
+            callback,
+            cancellation,
+        };
+        reporter.report(0);
+        reporter
+    }
+
+    /// Mark one more step as done.
+    pub(crate) fn step(&self) {
+        let mut done = self.done.lock().unwrap();
+        *done += 1;
+        log::info!(
+            "{}: {:.2}%",
+            self.stage,
+            (*done as f32) / (self.total as f32) * 100.0
+        );
+        self.report(*done);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn report(&self, done: usize) {
+        if let Some(callback) = self.callback {
+            let fraction = match self.total {
+                0 => 1.0,
+                total => done as f32 / total as f32,
+            };
+            callback(Progress {
+                stage: self.stage,
+                fraction,
+            });
+        }
+    }
+}
+
+impl fmt::Debug for Reporter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reporter")
+            .field("stage", &self.stage)
+            .field("total", &self.total)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn reports_steps_in_order() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let callback: ProgressCallback = Arc::new(move |p: Progress| sink.lock().unwrap().push(p));
+        let reporter = Reporter::new("Testing", 1000, Some(&callback), None);
+        (0..1000).into_par_iter().for_each(|_| reporter.step());
+
+        let fractions: Vec<_> = reports.lock().unwrap().iter().map(|p| p.fraction).collect();
+        assert_eq!(fractions.len(), 1001);
+        assert!(fractions.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(fractions.last(), Some(&1.0));
+    }
+}
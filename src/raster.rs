@@ -0,0 +1,197 @@
+//! A minimal RGB image with rectangles, lines and a built in 3x5 pixel font, written as PNG.
+//! Used to draw build guides and previews without depending on a font or graphics library.
+
+use crate::error::{Error, Result};
+use std::io::BufWriter;
+
+pub type Rgb = [u8; 3];
+
+pub const WHITE: Rgb = [255, 255, 255];
+pub const BLACK: Rgb = [0, 0, 0];
+
+/// Glyphs 3 pixels wide and 5 tall, one row per byte with the leftmost pixel in bit 2.
+/// Letters are drawn in capitals whatever their case.
+const GLYPHS: [(char, [u8; 5]); 49] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+];
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|(g, _)| *g == c)
+        .or_else(|| GLYPHS.iter().find(|(g, _)| *g == '?'))
+        .unwrap()
+        .1
+}
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// An image filled with one colour.
+    pub fn new(width: u32, height: u32, background: Rgb) -> Self {
+        Self {
+            width,
+            height,
+            pixels: background
+                .iter()
+                .copied()
+                .cycle()
+                .take(width as usize * height as usize * 3)
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Rgb {
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    /// Colour a pixel. Pixels outside the image are ignored.
+    pub fn set(&mut self, x: i64, y: i64, colour: Rgb) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&colour);
+    }
+
+    /// Mix a colour into a pixel, e.g. for glass, where `alpha` of 1 replaces the pixel.
+    pub fn blend(&mut self, x: i64, y: i64, colour: Rgb, alpha: f32) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let old = self.get(x as u32, y as u32);
+        let mut mixed = [0; 3];
+        for i in 0..3 {
+            mixed[i] = (old[i] as f32 * (1.0 - alpha) + colour[i] as f32 * alpha).round() as u8;
+        }
+        self.set(x, y, mixed);
+    }
+
+    pub fn fill_rect(&mut self, x: i64, y: i64, width: u32, height: u32, colour: Rgb) {
+        for j in y..y + height as i64 {
+            for i in x..x + width as i64 {
+                self.set(i, j, colour);
+            }
+        }
+    }
+
+    /// How many pixels wide `text` is when drawn at `scale`.
+    pub fn text_width(text: &str, scale: u32) -> u32 {
+        (text.chars().count() as u32 * 4).saturating_sub(1) * scale
+    }
+
+    /// How many pixels tall a line of text is when drawn at `scale`.
+    pub fn text_height(scale: u32) -> u32 {
+        5 * scale
+    }
+
+    /// Draw `text` with its top left corner at `x`, `y`, with each font pixel `scale` pixels across.
+    pub fn draw_text(&mut self, x: i64, y: i64, text: &str, scale: u32, colour: Rgb) {
+        for (n, c) in text.chars().enumerate() {
+            let left = x + (n as u32 * 4 * scale) as i64;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.fill_rect(
+                            left + (column * scale) as i64,
+                            y + (row as u32 * scale) as i64,
+                            scale,
+                            scale,
+                            colour,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn write_png(&self, path: &str) -> Result<()> {
+        let file = std::fs::File::create(path).map_err(|e| Error::io(path, e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+        let to_io = |e: png::EncodingError| match e {
+            png::EncodingError::IoError(e) => e,
+            e => std::io::Error::other(e),
+        };
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|e| Error::io(path, to_io(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_text() {
+        let mut image = Image::new(20, 10, WHITE);
+        image.draw_text(1, 1, "T1", 1, BLACK);
+        assert_eq!(Image::text_width("T1", 1), 7);
+        // The top bar of the T, and the base of the 1
+        assert_eq!(image.get(1, 1), BLACK);
+        assert_eq!(image.get(3, 1), BLACK);
+        assert_eq!(image.get(1, 2), WHITE);
+        assert_eq!(image.get(7, 5), BLACK);
+        // Lowercase letters are drawn as capitals
+        let mut lower = Image::new(20, 10, WHITE);
+        lower.draw_text(1, 1, "t1", 1, BLACK);
+        assert_eq!(lower.pixels, image.pixels);
+    }
+}
@@ -1,43 +1,149 @@
-use crate::readers::reader::Reader;
-use anyhow::Error;
+use crate::error::{Error, Result};
+use crate::readers::reader::{build_trimesh, MeshPart, Reader};
+use crate::transform::{Axis, CoordinateSystem};
 use collada::document::ColladaDocument;
-use nalgebra::Point3;
+use nalgebra::{Matrix4, Point3};
 use parry3d::shape::TriMesh;
+use std::collections::HashMap;
 use std::path::Path;
 
 /// The Collada reader.
 /// If multiple polys are found, they are all mushed together.
 pub struct DaeReader;
 
+fn read_document(path: &str) -> Result<ColladaDocument> {
+    ColladaDocument::from_path(Path::new(path)).map_err(|s| Error::parse(path, s))
+}
+
+/// Find the transform of every scene node that instances a geometry, keyed by the geometry's id.
+/// Exporters like Blender store the up axis conversion in the node's `<matrix>` rather than in the
+/// vertices, so ignoring it would leave the model rotated relative to the document's up axis.
+fn geometry_transforms(doc: &ColladaDocument) -> HashMap<String, Vec<Matrix4<f32>>> {
+    let ns = doc.root_element.ns.as_deref();
+    let mut transforms = HashMap::new();
+    let mut stack = Vec::new();
+    if let Some(scenes) = doc.root_element.get_child("library_visual_scenes", ns) {
+        for scene in scenes.get_children("visual_scene", ns) {
+            for node in scene.get_children("node", ns) {
+                stack.push((node, Matrix4::identity()));
+            }
+        }
+    }
+
+    while let Some((node, parent)) = stack.pop() {
+        let mut transform = parent;
+        for matrix in node.get_children("matrix", ns) {
+            let values: Vec<f32> = matrix
+                .content_str()
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            if values.len() == 16 {
+                transform *= Matrix4::from_row_slice(&values);
+            }
+        }
+        for instance in node.get_children("instance_geometry", ns) {
+            if let Some(url) = instance.get_attribute("url", None) {
+                transforms
+                    .entry(url.trim_start_matches('#').to_string())
+                    .or_insert_with(Vec::new)
+                    .push(transform);
+            }
+        }
+        for child in node.get_children("node", ns) {
+            stack.push((child, transform));
+        }
+    }
+
+    transforms
+}
+
 impl Reader for DaeReader {
-    fn load(&self, path: &str) -> Result<TriMesh, Error> {
-        let doc =
-            ColladaDocument::from_path(&Path::new(path)).map_err(|s| anyhow::anyhow!("{}", s))?;
+    fn load(&self, path: &str) -> Result<TriMesh> {
+        self.load_parts(path).map(|(trimesh, _)| trimesh)
+    }
+
+    /// Each group of triangles is a part, named after its geometry and material.
+    fn load_parts(&self, path: &str) -> Result<(TriMesh, Vec<MeshPart>)> {
+        let doc = read_document(path)?;
+        let transforms = geometry_transforms(&doc);
         let mut triangles: Vec<[u32; 3]> = Vec::new();
         let mut vertices: Vec<Point3<f32>> = Vec::new();
+        let mut parts = Vec::new();
 
         if let Some(objset) = doc.get_obj_set() {
-            let offset = vertices.len() as u32;
             for object in objset.objects {
-                object
-                    .vertices
-                    .iter()
-                    .for_each(|v| vertices.push(Point3::new(v.x as f32, v.y as f32, v.z as f32)));
-                object.geometry.iter()
-                    .flat_map(|g| &g.mesh)
-                    .for_each(|el| match el {
-                        collada::PrimitiveElement::Polylist(_) => panic!("Discovered a polylist in the collada file. Polylists are not supported."),
-                        collada::PrimitiveElement::Triangles(tris) => {
-                            for tri in &tris.vertices {
-                                triangles.push([tri.0 as u32 + offset, tri.1 as u32 + offset, tri.2 as u32 + offset]);
+                // Geometry that isn't placed in the scene is still loaded as-is
+                let instances = transforms
+                    .get(&object.id)
+                    .cloned()
+                    .unwrap_or_else(|| vec![Matrix4::identity()]);
+                for transform in instances {
+                    let offset = vertices.len() as u32;
+                    object.vertices.iter().for_each(|v| {
+                        let p = Point3::new(v.x as f32, v.y as f32, v.z as f32);
+                        vertices.push(transform.transform_point(&p))
+                    });
+                    for el in object.geometry.iter().flat_map(|g| &g.mesh) {
+                        match el {
+                            collada::PrimitiveElement::Polylist(_) => {
+                                return Err(Error::parse(
+                                    path,
+                                    "Discovered a polylist in the collada file. Polylists are not supported, re-export the model with triangulated faces.",
+                                ))
+                            }
+                            collada::PrimitiveElement::Triangles(tris) => {
+                                let first = triangles.len() as u32;
+                                for tri in &tris.vertices {
+                                    triangles.push([tri.0 as u32 + offset, tri.1 as u32 + offset, tri.2 as u32 + offset]);
+                                }
+                                parts.push(MeshPart {
+                                    name: object.name.clone(),
+                                    material: tris.material.clone(),
+                                    triangles: first..triangles.len() as u32,
+                                });
                             }
                         }
-                    });
+                    }
+                }
             }
 
-            Ok(TriMesh::new(vertices, triangles))
+            Ok((build_trimesh(path, vertices, triangles)?, parts))
         } else {
-            Err(anyhow::anyhow!("Did not find object set"))
+            Err(Error::parse(path, "Did not find object set"))
         }
     }
+
+    /// COLLADA records the up axis in `<asset><up_axis>`, and defaults to Y-up if it's missing.
+    fn coordinate_system(&self, path: &str) -> Result<CoordinateSystem> {
+        let doc = read_document(path)?;
+        let ns = doc.root_element.ns.as_deref();
+        let up = doc
+            .root_element
+            .get_child("asset", ns)
+            .and_then(|asset| asset.get_child("up_axis", ns))
+            .map(|el| el.content_str().parse::<Axis>())
+            .transpose()?
+            .unwrap_or(Axis::Y);
+        Ok(CoordinateSystem {
+            up,
+            ..Default::default()
+        })
+    }
+
+    /// COLLADA records the length of a unit in `<asset><unit meter="...">`, and defaults to metres if it's missing.
+    fn metres_per_unit(&self, path: &str) -> Result<Option<f32>> {
+        let doc = read_document(path)?;
+        let ns = doc.root_element.ns.as_deref();
+        let metres = doc
+            .root_element
+            .get_child("asset", ns)
+            .and_then(|asset| asset.get_child("unit", ns))
+            .and_then(|unit| unit.get_attribute("meter", None))
+            .map(|m| m.trim().parse::<f32>())
+            .transpose()
+            .map_err(|e| Error::parse(path, e))?
+            .unwrap_or(1.0);
+        Ok(Some(metres))
+    }
 }
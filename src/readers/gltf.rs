@@ -1,44 +1,92 @@
-use crate::readers::reader::Reader;
-use anyhow::Error;
-use nalgebra::Point3;
+use crate::error::{Error, Result};
+use crate::readers::reader::{build_trimesh, MeshPart, Reader};
+use gltf::buffer::Data;
+use gltf::Mesh;
+use nalgebra::{Matrix4, Point3};
 use parry3d::shape::TriMesh;
-use std::collections::BTreeMap;
 
 pub struct GltfReader;
 
 impl Reader for GltfReader {
-    fn load(&self, path: &str) -> Result<TriMesh, Error> {
-        let (gltf, buffers, _) = gltf::import(path)?;
-        let mut triangles: Vec<[u32; 3]> = Vec::new();
-        let mut vertices: Vec<Point3<f32>> = Vec::new();
-        // Store whether a vertex exists and if yes, which index
-        // let mut vertex_lookup = BTreeMap::new();
-        for mesh in gltf.meshes() {
-            for primitive in mesh.primitives() {
-                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                if let Some(iter) = reader.read_positions() {
-                    let positions: Vec<_> = iter.collect();
-                    for face in positions.chunks(3) {
-                        // let mut buffer = Vec::new();
-                        // for pos in face {
-                        //     let pos_bits = (pos[0].to_bits(), pos[1].to_bits(), pos[2].to_bits());
-                        //     let index = vertex_lookup.entry(pos_bits)
-                        //         .or_insert_with(|| {
-                        //             vertices.push(Point3::new(pos[0], pos[1], pos[2]));
-                        //             vertices.len() - 1
-                        //         });
-                        //     buffer.push(*index);
-                        // }
-                        let i = vertices.len();
-                        for pos in face {
-                            vertices.push(Point3::new(pos[0], pos[1], pos[2]));
-                        }
-                        triangles.push([i as u32, (i + 1) as u32, (i + 2) as u32]);
+    fn load(&self, path: &str) -> Result<TriMesh> {
+        self.load_parts(path).map(|(trimesh, _)| trimesh)
+    }
+
+    /// Each primitive is a part, named after its node (or mesh) and material.
+    fn load_parts(&self, path: &str) -> Result<(TriMesh, Vec<MeshPart>)> {
+        let (gltf, buffers, _) = gltf::import(path).map_err(|e| Error::parse(path, e))?;
+        let mut mesh = MeshBuilder::default();
+
+        // Meshes are placed by the scene's nodes, and exporters like Blender put the conversion to
+        // glTF's Y-up into the node transforms. Only fall back to the raw meshes if there's no scene.
+        match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => {
+                let mut stack: Vec<_> = scene
+                    .nodes()
+                    .map(|node| (node, Matrix4::identity()))
+                    .collect();
+                while let Some((node, parent)) = stack.pop() {
+                    let transform = parent * Matrix4::from(node.transform().matrix());
+                    if let Some(m) = node.mesh() {
+                        let name = node.name().or_else(|| m.name()).unwrap_or_default();
+                        mesh.read(&m, name, &buffers, &transform);
                     }
+                    stack.extend(node.children().map(|child| (child, transform)));
+                }
+            }
+            None => {
+                for m in gltf.meshes() {
+                    let name = m.name().unwrap_or_default();
+                    mesh.read(&m, name, &buffers, &Matrix4::identity());
                 }
             }
         }
 
-        Ok(TriMesh::new(vertices, triangles))
+        let trimesh = build_trimesh(path, mesh.vertices, mesh.triangles)?;
+        Ok((trimesh, mesh.parts))
+    }
+
+    /// glTF always uses metres.
+    fn metres_per_unit(&self, _path: &str) -> Result<Option<f32>> {
+        Ok(Some(1.0))
+    }
+}
+
+/// Collects the triangles of every mesh in the scene.
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<Point3<f32>>,
+    triangles: Vec<[u32; 3]>,
+    parts: Vec<MeshPart>,
+}
+
+impl MeshBuilder {
+    /// Append the mesh's triangles, transformed by `transform`.
+    fn read(&mut self, mesh: &Mesh, name: &str, buffers: &[Data], transform: &Matrix4<f32>) {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            if let Some(iter) = reader.read_positions() {
+                let offset = self.vertices.len() as u32;
+                let first = self.triangles.len() as u32;
+                self.vertices.extend(
+                    iter.map(|pos| transform.transform_point(&Point3::new(pos[0], pos[1], pos[2]))),
+                );
+                let count = self.vertices.len() as u32 - offset;
+                // Non-indexed primitives list their vertices in order
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..count).collect(),
+                };
+                for face in indices.chunks_exact(3) {
+                    self.triangles
+                        .push([face[0] + offset, face[1] + offset, face[2] + offset]);
+                }
+                self.parts.push(MeshPart {
+                    name: name.to_string(),
+                    material: primitive.material().name().map(str::to_string),
+                    triangles: first..self.triangles.len() as u32,
+                });
+            }
+        }
     }
 }
@@ -1,38 +1,51 @@
-use crate::readers::reader::Reader;
+use crate::error::{Error, Result};
+use crate::readers::reader::{build_trimesh, MeshPart, Reader};
 use parry3d::na::Point3;
 use parry3d::shape::TriMesh;
-use tobj::{load_obj, Model};
+use tobj::{load_obj, Material, Model};
 
 pub struct ObjReader;
 
 impl Reader for ObjReader {
-    fn load(&self, path: &str) -> Result<TriMesh, anyhow::Error> {
-        let model = read_obj(path)?;
-        Ok(obj_to_trimesh(model))
+    fn load(&self, path: &str) -> Result<TriMesh> {
+        self.load_parts(path).map(|(trimesh, _)| trimesh)
+    }
+
+    /// Each object or group is a part, named after its material if it has one.
+    fn load_parts(&self, path: &str) -> Result<(TriMesh, Vec<MeshPart>)> {
+        let (models, materials) = read_obj(path)?;
+        obj_to_trimesh(path, models, &materials)
     }
 }
 
-fn read_obj(path: &str) -> anyhow::Result<Vec<Model>> {
-    let (model, _) = load_obj(path, true).map_err(|e| {
+fn read_obj(path: &str) -> Result<(Vec<Model>, Vec<Material>)> {
+    load_obj(path, true).map_err(|e| {
         log::error!("Could not open file {}: {:?}", path, e);
-        e
-    })?;
-    Ok(model)
+        Error::parse(path, e)
+    })
 }
 
 /// Convert the output of tobj into one big trimesh
-fn obj_to_trimesh(objs: Vec<Model>) -> TriMesh {
+fn obj_to_trimesh(
+    path: &str,
+    objs: Vec<Model>,
+    materials: &[Material],
+) -> Result<(TriMesh, Vec<MeshPart>)> {
     let mut points: Vec<Point3<f32>> = vec![];
     let mut indices: Vec<[u32; 3]> = vec![];
+    let mut parts = Vec::new();
 
     for obj in objs.into_iter() {
         let mesh = obj.mesh;
+        // Each object's indices start from its own first vertex
+        let offset = points.len() as u32;
+        let first = indices.len() as u32;
 
         let mut i: usize = 0;
         while i as i32 <= (mesh.indices.len() as i32) - 3 {
-            let i1 = mesh.indices[i];
-            let i2 = mesh.indices[i + 1];
-            let i3 = mesh.indices[i + 2];
+            let i1 = mesh.indices[i] + offset;
+            let i2 = mesh.indices[i + 1] + offset;
+            let i3 = mesh.indices[i + 2] + offset;
             indices.push([i1, i2, i3]);
             i += 3;
         }
@@ -45,7 +58,36 @@ fn obj_to_trimesh(objs: Vec<Model>) -> TriMesh {
             points.push(Point3::from([p1, p2, p3]));
             i += 3;
         }
+
+        parts.push(MeshPart {
+            name: obj.name,
+            material: mesh
+                .material_id
+                .and_then(|id| materials.get(id))
+                .map(|m| m.name.clone()),
+            triangles: first..indices.len() as u32,
+        });
     }
 
-    TriMesh::new(points, indices)
+    Ok((build_trimesh(path, points, indices)?, parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_the_indices_of_each_object() {
+        // tobj numbers each object's vertices from zero
+        let path =
+            std::env::temp_dir().join(format!("threed2vox-objects-{}.obj", std::process::id()));
+        std::fs::write(
+            &path,
+            "o A\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\no B\nv 0 0 5\nv 1 0 5\nv 0 1 5\nf 4 5 6\n",
+        )
+        .unwrap();
+        let trimesh = ObjReader.load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(trimesh.indices(), &[[0, 1, 2], [3, 4, 5]]);
+    }
 }
@@ -1,7 +1,62 @@
+use crate::error::{Error, Result};
+use crate::transform::CoordinateSystem;
+use nalgebra::Point3;
 use parry3d::shape::TriMesh;
+use std::ops::Range;
+
+/// A named group of triangles in a loaded mesh, e.g. an OBJ object or a glTF node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshPart {
+    /// The name of the object, node or geometry
+    pub name: String,
+    /// The name of the part's material, if it has one
+    pub material: Option<String>,
+    /// The indices of the part's triangles in the TriMesh
+    pub triangles: Range<u32>,
+}
+
+impl MeshPart {
+    /// Whether the part or its material has this name, ignoring case.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .material
+                .as_deref()
+                .is_some_and(|m| m.eq_ignore_ascii_case(name))
+    }
+}
 
 /// Used for types that can read from various 3D files and output a trimesh
 pub trait Reader {
     /// Load a file and return a TriMesh
-    fn load(&self, path: &str) -> Result<TriMesh, anyhow::Error>;
+    fn load(&self, path: &str) -> Result<TriMesh>;
+
+    /// Load a file along with the named parts of the mesh, in order of their triangles.
+    /// Formats that don't name their parts return none.
+    fn load_parts(&self, path: &str) -> Result<(TriMesh, Vec<MeshPart>)> {
+        Ok((self.load(path)?, Vec::new()))
+    }
+
+    /// The coordinate system the file was authored in.
+    /// Formats that don't record it should return whatever their exporters usually use.
+    fn coordinate_system(&self, _path: &str) -> Result<CoordinateSystem> {
+        Ok(CoordinateSystem::default())
+    }
+
+    /// How many metres one unit in the file is, if the format records it.
+    fn metres_per_unit(&self, _path: &str) -> Result<Option<f32>> {
+        Ok(None)
+    }
+}
+
+/// Build a TriMesh from what a reader found, failing if there are no triangles.
+pub(crate) fn build_trimesh(
+    path: &str,
+    vertices: Vec<Point3<f32>>,
+    triangles: Vec<[u32; 3]>,
+) -> Result<TriMesh> {
+    if triangles.is_empty() {
+        return Err(Error::EmptyMesh(path.to_string()));
+    }
+    Ok(TriMesh::new(vertices, triangles))
 }
@@ -0,0 +1,372 @@
+//! Scenes of several models, each placed with its own transform and blocks, voxelised into one grid.
+//!
+//! A scene is described by a TOML manifest:
+//!
+//! ```toml
+//! # Model units per block, shared by every model. Defaults to the --size or --scale options.
+//! voxel_size = 0.5
+//!
+//! [[model]]
+//! path = "walls.obj"
+//! block = "stone_bricks:80,cracked_stone_bricks:20"
+//!
+//! [[model]]
+//! path = "props/table.gltf"
+//! translate = [4, 0, 2]
+//! rotate = [0, 90, 0]
+//! scale = 0.5
+//! priority = 1
+//! [model.materials]
+//! Tabletop = "oak_slab[type=top]"
+//! ```
+
+use crate::boolean::{combine, BooleanOp};
+use crate::config::reader_for_extension;
+use crate::error::{Error, Result};
+use crate::mix::{map_materials, BlockMix};
+use crate::transform::{Axis, CoordinateSystem, Handedness, Transform};
+use crate::voxel_grid::VoxelGrid;
+use crate::voxelizer::{VoxelOption, Voxelizer};
+use nalgebra::Vector3;
+use parry3d::bounding_volume::BoundingVolume;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The layout of a scene manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    voxel_size: Option<f32>,
+    #[serde(rename = "model", default)]
+    models: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelEntry {
+    path: String,
+    block: Option<String>,
+    #[serde(default)]
+    priority: i32,
+    translate: Option<[f32; 3]>,
+    rotate: Option<[f32; 3]>,
+    scale: Option<Scale>,
+    up_axis: Option<String>,
+    handedness: Option<String>,
+    #[serde(default)]
+    materials: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scale {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+/// One model in a scene.
+#[derive(Clone, Debug)]
+pub struct SceneModel {
+    /// The model file. The manifest gives it relative to itself, and `Scene::parse` resolves it against `base`.
+    pub path: String,
+    /// Where the model is placed in the scene
+    pub transform: Transform,
+    /// Overrides the up axis the reader assumes for the file
+    pub up_axis: Option<Axis>,
+    /// Overrides the handedness the reader assumes for the file
+    pub handedness: Option<Handedness>,
+    /// The blocks for the model's voxels. Voxels without one use the output's block.
+    pub block: Option<BlockMix>,
+    /// Blocks for the model's named parts or materials, which take precedence over `block`
+    pub materials: Vec<(String, BlockMix)>,
+    /// Where models overlap, the one with the higher priority keeps its blocks. Ties go to the model listed first.
+    pub priority: i32,
+}
+
+/// Several models voxelised into one shared grid.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    /// Model units per block, shared by every model. If unset, the size is worked out from the whole scene.
+    pub voxel_size: Option<f32>,
+    pub models: Vec<SceneModel>,
+}
+
+impl Scene {
+    /// Read a scene manifest. Model paths are relative to the manifest.
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&content, base).map_err(|e| match e {
+            Error::Parse { message, .. } => Error::parse(path, message),
+            e => e,
+        })
+    }
+
+    /// Parse a scene manifest, resolving model paths relative to `base`.
+    pub fn parse(content: &str, base: &Path) -> Result<Self> {
+        let file: SceneFile = toml::from_str(content).map_err(|e| Error::parse("scene", e))?;
+        if file.models.is_empty() {
+            return Err(Error::parse("scene", "The scene has no [[model]] entries"));
+        }
+        let models = file
+            .models
+            .into_iter()
+            .map(|entry| {
+                let mut transform = Transform::default();
+                if let Some([x, y, z]) = entry.rotate {
+                    transform.rotation = Transform::rotation_from_degrees(x, y, z);
+                }
+                if let Some([x, y, z]) = entry.translate {
+                    transform.translation = Vector3::new(x, y, z);
+                }
+                transform.scale = match entry.scale {
+                    Some(Scale::Uniform(s)) => Vector3::repeat(s),
+                    Some(Scale::PerAxis([x, y, z])) => Vector3::new(x, y, z),
+                    None => Vector3::repeat(1.0),
+                };
+                let materials = entry
+                    .materials
+                    .into_iter()
+                    .map(|(name, block)| Ok((name, block.parse()?)))
+                    .collect::<Result<_>>()?;
+                Ok(SceneModel {
+                    path: base.join(&entry.path).to_string_lossy().into_owned(),
+                    transform,
+                    up_axis: entry.up_axis.as_deref().map(str::parse).transpose()?,
+                    handedness: entry.handedness.as_deref().map(str::parse).transpose()?,
+                    block: entry.block.as_deref().map(str::parse).transpose()?,
+                    materials,
+                    priority: entry.priority,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            voxel_size: file.voxel_size,
+            models,
+        })
+    }
+
+    /// Replace every block mix in the scene, e.g. to validate their blocks.
+    pub fn try_map(mut self, f: impl Fn(BlockMix) -> Result<BlockMix>) -> Result<Self> {
+        for model in &mut self.models {
+            model.block = model.block.take().map(&f).transpose()?;
+            for (_, mix) in &mut model.materials {
+                *mix = f(mix.clone())?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Voxelise every model into one grid. Without a `voxel_size` in the manifest, `size` is applied to the
+    /// bounds of the whole scene, e.g. so `--size 64` makes the whole scene 64 blocks long.
+    /// For `VoxelOption::RealScale`, each model is scaled into metres using the units its file records, or
+    /// `metres_per_unit` if it doesn't record any.
+    pub fn voxelize(&self, size: VoxelOption, metres_per_unit: Option<f32>) -> Result<VoxelGrid> {
+        self.voxelize_with(size, metres_per_unit, Voxelizer::new())
+    }
+
+    /// Like `voxelize`, but each model is voxelised by a copy of `voxelizer` with its own size, transform and
+    /// coordinate system, e.g. to report progress or be cancelled.
+    pub fn voxelize_with(
+        &self,
+        size: VoxelOption,
+        metres_per_unit: Option<f32>,
+        voxelizer: Voxelizer,
+    ) -> Result<VoxelGrid> {
+        let real_scale = self.voxel_size.is_none() && matches!(size, VoxelOption::RealScale(_));
+        let mut meshes = Vec::new();
+        for model in &self.models {
+            log::info!("Loading {}.", model.path);
+            let extension = Path::new(&model.path).extension().unwrap_or_default();
+            let reader = reader_for_extension(&extension.to_string_lossy())?;
+            let mut coordinate_system: CoordinateSystem = reader.coordinate_system(&model.path)?;
+            if let Some(up) = model.up_axis {
+                coordinate_system.up = up;
+            }
+            if let Some(handedness) = model.handedness {
+                coordinate_system.handedness = handedness;
+            }
+            let mut transform = model.transform.clone();
+            if real_scale {
+                let metres = match reader.metres_per_unit(&model.path)?.or(metres_per_unit) {
+                    Some(metres) => metres,
+                    None => {
+                        log::warn!("The units of {} are unknown, assuming metres.", model.path);
+                        1.0
+                    }
+                };
+                transform.scale *= metres;
+                transform.translation *= metres;
+            }
+            let (trimesh, parts) = reader.load_parts(&model.path)?;
+            meshes.push((model, transform, coordinate_system, trimesh, parts));
+        }
+
+        let voxel_size = match self.voxel_size {
+            Some(voxel_size) => voxel_size,
+            None => {
+                let aabb = meshes
+                    .iter()
+                    .map(|(_, transform, coordinate_system, trimesh, _)| {
+                        let trimesh = coordinate_system.convert_mesh(trimesh.clone());
+                        let trimesh = transform.scale_mesh(trimesh);
+                        trimesh.aabb(&transform.isometry())
+                    })
+                    .reduce(|a, b| a.merged(&b))
+                    .unwrap();
+                // Models were scaled into metres above
+                size.voxel_size(&aabb.extents(), || 1.0)
+            }
+        };
+
+        let mut grids = Vec::new();
+        for (model, transform, coordinate_system, trimesh, parts) in meshes {
+            let mut grid = voxelizer
+                .clone()
+                .voxel_size(VoxelOption::VoxelSize(voxel_size))
+                .transform(transform)
+                .coordinate_system(coordinate_system)
+                .parts(parts)
+                .voxelize(trimesh)?;
+            map_materials(&mut grid, &model.materials);
+            if let Some(mix) = &model.block {
+                mix.apply(&mut grid);
+            }
+            grids.push((model.priority, grid));
+        }
+        compose(grids)
+    }
+}
+
+/// Unite the grids, keeping the blocks of the grid with the highest priority where they overlap.
+fn compose(mut grids: Vec<(i32, VoxelGrid)>) -> Result<VoxelGrid> {
+    // Uniting keeps the blocks already in the grid, so start with the highest priority
+    grids.sort_by_key(|(priority, _)| std::cmp::Reverse(*priority));
+    let mut grids = grids.into_iter().map(|(_, grid)| grid);
+    let mut scene = grids
+        .next()
+        .ok_or_else(|| Error::invalid_argument("scene", "The scene has no models"))?;
+    for grid in grids {
+        combine(&mut scene, &grid, BooleanOp::Union)?;
+    }
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BlockState;
+
+    #[test]
+    fn parses_manifests() {
+        let scene = Scene::parse(
+            r#"
+            voxel_size = 0.5
+
+            [[model]]
+            path = "walls.obj"
+            block = "stone:3,andesite"
+
+            [[model]]
+            path = "props/table.gltf"
+            translate = [4, 0, 2]
+            rotate = [0, 90, 0]
+            scale = [1, 2, 1]
+            up_axis = "z"
+            priority = 1
+            [model.materials]
+            Tabletop = "oak_slab[type=top]"
+            "#,
+            Path::new("scenes"),
+        )
+        .unwrap();
+        assert_eq!(scene.voxel_size, Some(0.5));
+        let table = &scene.models[1];
+        assert_eq!(
+            Path::new(&table.path),
+            Path::new("scenes").join("props/table.gltf")
+        );
+        assert_eq!(table.transform.translation, Vector3::new(4.0, 0.0, 2.0));
+        assert_eq!(table.transform.scale, Vector3::new(1.0, 2.0, 1.0));
+        assert_eq!(table.up_axis, Some(Axis::Z));
+        assert_eq!(table.priority, 1);
+        assert_eq!(table.materials[0].0, "Tabletop");
+
+        assert!(Scene::parse("voxel_size = 1", Path::new("")).is_err());
+        assert!(Scene::parse("[[model]]\npath = \"a.obj\"\ncolour = 1", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn keeps_the_blocks_of_higher_priority_models() {
+        let model = |x: i32, block: &str| {
+            let mut grid = VoxelGrid::new(2, 1, 1);
+            grid.origin.x = x as f32;
+            grid.set_block(0, 0, 0, BlockState::new(block));
+            grid.set_block(1, 0, 0, BlockState::new(block));
+            grid
+        };
+        let scene = compose(vec![
+            (0, model(0, "stone")),
+            (2, model(1, "glass")),
+            (2, model(2, "dirt")),
+        ])
+        .unwrap();
+        assert_eq!(scene.dimensions, (4, 1, 1));
+        assert_eq!(scene.block(0, 0, 0), Some(&BlockState::new("stone")));
+        assert_eq!(scene.block(1, 0, 0), Some(&BlockState::new("glass")));
+        assert_eq!(scene.block(2, 0, 0), Some(&BlockState::new("glass")));
+        assert_eq!(scene.block(3, 0, 0), Some(&BlockState::new("dirt")));
+    }
+
+    #[test]
+    fn scales_each_model_by_its_own_units() {
+        // The same teapot, once in metres and once in centimetres
+        let dir = std::env::temp_dir().join(format!("threed2vox-scene-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let teapot = std::fs::read_to_string("models/teapot.dae").unwrap();
+        std::fs::write(dir.join("teapot_m.dae"), &teapot).unwrap();
+        let teapot = teapot.replace(r#"meter="1""#, r#"meter="0.01""#);
+        std::fs::write(dir.join("teapot_cm.dae"), teapot).unwrap();
+
+        let dimensions = |file: &str, blocks_per_metre: f32, metres_per_unit: Option<f32>| {
+            let manifest = format!("[[model]]\npath = {:?}", file);
+            Scene::parse(&manifest, &dir)
+                .unwrap()
+                .voxelize(VoxelOption::RealScale(blocks_per_metre), metres_per_unit)
+                .unwrap()
+                .dimensions
+        };
+        // The file's units win over --units, which is only for files without any
+        assert_eq!(
+            dimensions("teapot_cm.dae", 100.0, None),
+            dimensions("teapot_m.dae", 1.0, Some(0.01))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn voxelizes_models_into_one_grid() {
+        let scene = Scene::parse(
+            r#"
+            [[model]]
+            path = "teapot.obj"
+            [[model]]
+            path = "teapot.gltf"
+            translate = [10, 0, 0]
+            block = "glass"
+            "#,
+            Path::new("models"),
+        )
+        .unwrap();
+        let grid = scene.voxelize(VoxelOption::MeshSize(20.0), None).unwrap();
+        // Two teapots side by side are twice as long as one, so each is about 10 blocks long
+        assert!(
+            (19..=22).contains(&grid.dimensions.0),
+            "{:?}",
+            grid.dimensions
+        );
+        assert!(grid.filled().any(|(x, y, z)| grid.block(x, y, z).is_none()));
+        assert!(grid.filled().any(|(x, y, z)| grid.block(x, y, z).is_some()));
+    }
+}
@@ -0,0 +1,220 @@
+//! Prints slices of the output as text, optionally coloured with ANSI escapes, to look at it in a terminal where a
+//! renderer can't be opened, e.g. on a headless build server.
+
+use crate::blocks::BlockState;
+use crate::error::{Error, Result};
+use crate::guide::label;
+use crate::preview::block_colour;
+use crate::transform::Axis;
+use crate::voxel_grid::VoxelGrid;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Characters for blocks whose first letter is already taken.
+const FALLBACK_SYMBOLS: &str = "#@%&*+=$~^0123456789";
+
+/// The character to draw each block in the grid with, the same in every slice so they can be compared.
+pub struct Legend {
+    symbols: HashMap<BlockState, char>,
+}
+
+impl Legend {
+    /// Pick a character for every block in the grid, preferring the first letter of its name so e.g. stone is
+    /// drawn as `s`. The most used blocks pick first. Voxels without a block use `default`.
+    pub fn new(grid: &VoxelGrid, default: &BlockState) -> Self {
+        let mut counts: HashMap<&BlockState, usize> = HashMap::new();
+        for (x, y, z) in grid.filled() {
+            *counts
+                .entry(grid.block(x, y, z).unwrap_or(default))
+                .or_default() += 1;
+        }
+        let mut blocks: Vec<_> = counts.into_iter().collect();
+        blocks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+
+        let mut symbols = HashMap::new();
+        let mut taken = vec!['.'];
+        for (block, _) in blocks {
+            let first = label(block).chars().next().unwrap_or('?');
+            let symbol = [first.to_ascii_lowercase(), first.to_ascii_uppercase()]
+                .iter()
+                .copied()
+                .chain(FALLBACK_SYMBOLS.chars())
+                .find(|c| !taken.contains(c))
+                .unwrap_or('?');
+            taken.push(symbol);
+            symbols.insert(block.clone(), symbol);
+        }
+        Self { symbols }
+    }
+
+    pub fn symbol(&self, block: &BlockState) -> char {
+        self.symbols.get(block).copied().unwrap_or('?')
+    }
+}
+
+/// How many slices the grid has along an axis.
+pub fn slice_count(grid: &VoxelGrid, axis: Axis) -> i32 {
+    let (dx, dy, dz) = grid.dimensions;
+    match axis {
+        Axis::X => dx,
+        Axis::Y => dy,
+        Axis::Z => dz,
+    }
+}
+
+/// Draw the slice at `index` along `axis`, two characters per block, followed by the blocks in it. Y slices are
+/// seen from above with north up, Z slices from the south and X slices from the east, like the previews.
+/// With `colour`, blocks are drawn on a background of their colour using 24-bit ANSI escapes.
+pub fn render_slice(
+    grid: &VoxelGrid,
+    default: &BlockState,
+    legend: &Legend,
+    axis: Axis,
+    index: i32,
+    colour: bool,
+) -> Result<String> {
+    let count = slice_count(grid, axis);
+    if !(0..count).contains(&index) {
+        return Err(Error::invalid_argument(
+            "layer",
+            format!(
+                "{:?} slices go from 0 to {}, not {}",
+                axis,
+                count - 1,
+                index
+            ),
+        ));
+    }
+    let (dx, dy, dz) = grid.dimensions;
+    // The rows and columns on screen, as grid coordinates
+    let (columns, rows, caption) = match axis {
+        Axis::Y => (dx, dz, "from above, north is up"),
+        Axis::Z => (dx, dy, "from the south, east is right"),
+        Axis::X => (dz, dy, "from the east, north is right"),
+    };
+    let position = |column: i32, row: i32| match axis {
+        Axis::Y => (column, index, row),
+        Axis::Z => (column, dy - 1 - row, index),
+        Axis::X => (index, dy - 1 - row, dz - 1 - column),
+    };
+    let row_label = |row: i32| match axis {
+        Axis::Y => row,
+        _ => dy - 1 - row,
+    };
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:?} = {} of 0 to {}, {}",
+        axis,
+        index,
+        count - 1,
+        caption
+    )
+    .unwrap();
+    let label_width = (rows - 1).max(0).to_string().len();
+    let mut counts: HashMap<&BlockState, usize> = HashMap::new();
+    for row in 0..rows {
+        write!(out, "{:>width$} ", row_label(row), width = label_width).unwrap();
+        for column in 0..columns {
+            let (x, y, z) = position(column, row);
+            if !grid.get(x, y, z) {
+                out.push_str(". ");
+                continue;
+            }
+            let block = grid.block(x, y, z).unwrap_or(default);
+            *counts.entry(block).or_default() += 1;
+            let symbol = legend.symbol(block);
+            if colour {
+                let [r, g, b] = block_colour(block);
+                // Black on light blocks and white on dark ones
+                let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+                let text = if luma > 128.0 { 30 } else { 97 };
+                write!(
+                    out,
+                    "\x1b[48;2;{};{};{};{}m{} \x1b[0m",
+                    r, g, b, text, symbol
+                )
+                .unwrap();
+            } else {
+                write!(out, "{} ", symbol).unwrap();
+            }
+        }
+        out.truncate(out.trim_end_matches(' ').len());
+        out.push('\n');
+    }
+
+    let mut blocks: Vec<_> = counts.into_iter().collect();
+    blocks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+    for (block, count) in blocks {
+        writeln!(out, "{} {} x{}", legend.symbol(block), label(block), count).unwrap();
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stone floor with a smooth stone block in it, and an oak log and a block of sand on it.
+    fn grid() -> VoxelGrid {
+        let mut grid = VoxelGrid::from_fn((3, 2, 2), |_, y, _| y == 0);
+        grid.set_block(0, 1, 0, BlockState::new("oak_log").with("axis", "y"));
+        grid.set_block(2, 1, 1, BlockState::new("sand"));
+        grid.set_block(1, 0, 1, BlockState::new("smooth_stone"));
+        grid
+    }
+
+    #[test]
+    fn picks_symbols_by_name() {
+        let stone = BlockState::new("stone");
+        let legend = Legend::new(&grid(), &stone);
+        assert_eq!(legend.symbol(&stone), 's');
+        assert_eq!(legend.symbol(&BlockState::new("sand")), 'S');
+        assert_eq!(legend.symbol(&BlockState::new("smooth_stone")), '#');
+        assert_eq!(
+            legend.symbol(&BlockState::new("oak_log").with("axis", "y")),
+            'o'
+        );
+        assert_eq!(legend.symbol(&BlockState::new("dirt")), '?');
+    }
+
+    #[test]
+    fn draws_slices_along_each_axis() {
+        let grid = grid();
+        let stone = BlockState::new("stone");
+        let legend = Legend::new(&grid, &stone);
+        let slice = |axis, index| render_slice(&grid, &stone, &legend, axis, index, false).unwrap();
+
+        let top = slice(Axis::Y, 1);
+        let lines: Vec<_> = top.lines().collect();
+        assert_eq!(lines[0], "Y = 1 of 0 to 1, from above, north is up");
+        assert_eq!(lines[1], "0 o . .");
+        assert_eq!(lines[2], "1 . . S");
+        assert_eq!(lines[3], "o oak_log[axis=y] x1");
+        assert_eq!(lines[4], "S sand x1");
+
+        // From the south, the top row is the highest layer
+        let front = slice(Axis::Z, 1);
+        assert_eq!(front.lines().nth(1), Some("1 . . S"));
+        assert_eq!(front.lines().nth(2), Some("0 s # s"));
+        // From the east, north is on the right
+        let side = slice(Axis::X, 0);
+        assert_eq!(side.lines().nth(1), Some("1 . o"));
+
+        assert!(render_slice(&grid, &stone, &legend, Axis::Y, 2, false).is_err());
+    }
+
+    #[test]
+    fn colours_blocks() {
+        let grid = grid();
+        let stone = BlockState::new("stone");
+        let legend = Legend::new(&grid, &stone);
+        let slice = render_slice(&grid, &stone, &legend, Axis::Y, 0, true).unwrap();
+        let [r, g, b] = block_colour(&stone);
+        assert!(slice.contains(&format!("\x1b[48;2;{};{};{};97ms \x1b[0m", r, g, b)));
+        assert!(!render_slice(&grid, &stone, &legend, Axis::Y, 0, false)
+            .unwrap()
+            .contains('\x1b'));
+    }
+}
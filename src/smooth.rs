@@ -0,0 +1,337 @@
+//! Replaces blocks on sloped surfaces with stairs and slabs of the same material,
+//! so slopes don't look like staircases of full blocks.
+
+use crate::blocks::{BlockRegistry, BlockState};
+use crate::version::MinecraftVersion;
+use crate::voxel_grid::VoxelGrid;
+use std::collections::HashMap;
+
+/// Bit masks of a voxel's eighths, as in `Surface::occupancy`.
+type Octants = u8;
+
+const FULL: Octants = 0xff;
+
+/// Surfaces whose normal is this close to an axis (the cosine of 10 degrees) are flat floors, ceilings or walls,
+/// which full blocks already follow.
+const AXIS_ALIGNED: f32 = 0.985;
+
+fn is_flat(grid: &VoxelGrid, (x, y, z): (i32, i32, i32)) -> bool {
+    grid.normal(x, y, z)
+        .is_some_and(|normal| normal.iter().any(|c| c.abs() > AXIS_ALIGNED))
+}
+
+/// Whether a voxel is on a flat surface, or on the edge where flat surfaces meet, e.g. a floor and a wall. The
+/// normal of an edge is the average of the faces' normals, so it looks sloped, but its neighbours on the faces don't.
+fn on_flat_surface(grid: &VoxelGrid, (x, y, z): (i32, i32, i32)) -> bool {
+    let neighbours = [
+        (x - 1, y, z),
+        (x + 1, y, z),
+        (x, y - 1, z),
+        (x, y + 1, z),
+        (x, y, z - 1),
+        (x, y, z + 1),
+    ];
+    is_flat(grid, (x, y, z)) || neighbours.iter().any(|&n| is_flat(grid, n))
+}
+
+fn octant(x: usize, y: usize, z: usize) -> Octants {
+    1 << (x | y << 1 | z << 2)
+}
+
+/// The four eighths of a horizontal layer.
+fn layer(y: usize) -> Octants {
+    octant(0, y, 0) | octant(1, y, 0) | octant(0, y, 1) | octant(1, y, 1)
+}
+
+/// The (x, z) quarters of a layer on one side of the voxel.
+fn side(facing: &str) -> [(usize, usize); 2] {
+    match facing {
+        "north" => [(0, 0), (1, 0)],
+        "south" => [(0, 1), (1, 1)],
+        "west" => [(0, 0), (0, 1)],
+        _ => [(1, 0), (1, 1)],
+    }
+}
+
+/// The sides to the left and right when looking towards `facing`.
+fn left_and_right(facing: &str) -> (&'static str, &'static str) {
+    match facing {
+        "north" => ("west", "east"),
+        "south" => ("east", "west"),
+        "west" => ("south", "north"),
+        _ => ("north", "south"),
+    }
+}
+
+/// Which eighths a stair fills. The tall part of a stair is on the side it faces.
+fn stair_octants(facing: &str, half: &str, shape: &str) -> Octants {
+    let (full_layer, step_layer) = match half {
+        "bottom" => (0, 1),
+        _ => (1, 0),
+    };
+    let back = side(facing);
+    let (left, right) = left_and_right(facing);
+    let quarters: Vec<(usize, usize)> = match shape {
+        "straight" => back.to_vec(),
+        "outer_left" => back
+            .iter()
+            .filter(|q| side(left).contains(q))
+            .copied()
+            .collect(),
+        "outer_right" => back
+            .iter()
+            .filter(|q| side(right).contains(q))
+            .copied()
+            .collect(),
+        "inner_left" => back.iter().chain(side(left).iter()).copied().collect(),
+        _ => back.iter().chain(side(right).iter()).copied().collect(),
+    };
+    quarters
+        .into_iter()
+        .fold(layer(full_layer), |octants, (x, z)| {
+            octants | octant(x, step_layer, z)
+        })
+}
+
+/// The stairs and slab made of the same material as a block, e.g. `stone_brick_stairs` for `stone_bricks`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Variants {
+    pub stairs: Option<BlockState>,
+    pub slab: Option<BlockState>,
+}
+
+impl Variants {
+    /// Look up the variants of `block` that exist in `version`.
+    pub fn of(block: &BlockState, registry: &BlockRegistry, version: &MinecraftVersion) -> Self {
+        let (namespace, path) = block.name.split_once(':').unwrap();
+        let exists = |name: &str| {
+            let id = format!("{}:{}", namespace, name);
+            registry
+                .get(&id)
+                .filter(|info| info.exists_in(version))
+                .map(|_| BlockState::new(&id))
+        };
+        // Planks and blocks drop the suffix, e.g. oak_stairs. Plurals drop the s, e.g. brick_stairs.
+        let bases = [
+            Some(path),
+            path.strip_suffix("_planks"),
+            path.strip_suffix("_block"),
+            path.strip_suffix('s'),
+        ];
+        bases
+            .iter()
+            .flatten()
+            .map(|base| Self {
+                stairs: exists(&format!("{}_stairs", base)),
+                slab: exists(&format!("{}_slab", base)),
+            })
+            .find(|variants| variants.stairs.is_some() || variants.slab.is_some())
+            .unwrap_or_default()
+    }
+
+    /// Every shape these variants can make, and which eighths of a voxel they fill.
+    fn shapes(&self) -> Vec<(BlockState, Octants)> {
+        let mut shapes = Vec::new();
+        if let Some(slab) = &self.slab {
+            shapes.push((slab.clone().with("type", "bottom"), layer(0)));
+            shapes.push((slab.clone().with("type", "top"), layer(1)));
+        }
+        if let Some(stairs) = &self.stairs {
+            for facing in &["north", "south", "west", "east"] {
+                for half in &["bottom", "top"] {
+                    for shape in &[
+                        "straight",
+                        "inner_left",
+                        "inner_right",
+                        "outer_left",
+                        "outer_right",
+                    ] {
+                        let state = stairs
+                            .clone()
+                            .with("facing", facing)
+                            .with("half", half)
+                            .with("shape", shape);
+                        shapes.push((state, stair_octants(facing, half, shape)));
+                    }
+                }
+            }
+        }
+        shapes
+    }
+}
+
+/// Replace blocks on sloped parts of the surface with the stair or slab that best matches which part of the voxel
+/// is inside the model, using `default` where the grid doesn't set a block. Blocks on flat or vertical parts of the
+/// surface and the edges between them, and blocks without stairs or slabs, are left alone. Returns how many voxels were changed.
+pub fn smooth_surface(
+    grid: &mut VoxelGrid,
+    default: &BlockState,
+    registry: &BlockRegistry,
+    version: &MinecraftVersion,
+) -> usize {
+    let mut shapes: HashMap<BlockState, Vec<(BlockState, Octants)>> = HashMap::new();
+    let mut changes = Vec::new();
+
+    for (x, y, z) in grid.filled() {
+        let surface = match grid.surface(x, y, z) {
+            Some(surface) => surface,
+            None => continue,
+        };
+        if on_flat_surface(grid, (x, y, z)) {
+            continue;
+        }
+        let occupied = surface
+            .occupancy()
+            .iter()
+            .enumerate()
+            .filter(|(_, &inside)| inside)
+            .fold(0, |octants, (i, _)| octants | 1 << i);
+        // The surface only grazes this voxel, so there's no telling which part of it should be filled
+        if occupied == 0 {
+            continue;
+        }
+
+        let block = grid.block(x, y, z).unwrap_or(default);
+        let shapes = shapes
+            .entry(block.clone())
+            .or_insert_with(|| Variants::of(block, registry, version).shapes());
+        let mismatch = |octants: Octants| (octants ^ occupied).count_ones();
+        let best = shapes
+            .iter()
+            .min_by_key(|(_, octants)| mismatch(*octants))
+            .filter(|(_, octants)| mismatch(*octants) < mismatch(FULL));
+        if let Some((state, _)) = best {
+            changes.push(((x, y, z), state.clone()));
+        }
+    }
+
+    let count = changes.len();
+    for ((x, y, z), block) in changes {
+        grid.set_block(x, y, z, block);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_grid::Surface;
+    use nalgebra::Vector3;
+
+    fn version() -> MinecraftVersion {
+        MinecraftVersion::parse("1.16").unwrap()
+    }
+
+    fn smooth(normal: Vector3<f32>, offset: f32) -> Option<BlockState> {
+        let mut grid = VoxelGrid::new(1, 1, 1);
+        grid.set(0, 0, 0, true);
+        let normal = normal.normalize();
+        grid.set_surface(0, 0, 0, Surface { normal, offset });
+        let registry = BlockRegistry::embedded();
+        smooth_surface(
+            &mut grid,
+            &BlockState::new("stone_bricks"),
+            &registry,
+            &version(),
+        );
+        grid.block(0, 0, 0).cloned()
+    }
+
+    #[test]
+    fn finds_stairs_and_slabs() {
+        let registry = BlockRegistry::embedded();
+        let variants = |name| Variants::of(&BlockState::new(name), &registry, &version());
+        assert_eq!(
+            variants("stone_bricks").stairs,
+            Some(BlockState::new("stone_brick_stairs"))
+        );
+        assert_eq!(
+            variants("oak_planks").slab,
+            Some(BlockState::new("oak_slab"))
+        );
+        assert_eq!(
+            variants("quartz_block").stairs,
+            Some(BlockState::new("quartz_stairs"))
+        );
+        assert_eq!(variants("glass"), Variants::default());
+    }
+
+    #[test]
+    fn fills_stairs_on_the_side_they_face() {
+        assert_eq!(
+            stair_octants("north", "bottom", "straight"),
+            layer(0) | octant(0, 1, 0) | octant(1, 1, 0)
+        );
+        let octants = stair_octants("east", "top", "outer_left");
+        assert_eq!(octants.count_ones(), 5);
+        assert_eq!(octants & layer(1), layer(1));
+        assert_eq!(octants & layer(0), octant(1, 0, 0));
+    }
+
+    #[test]
+    fn leaves_flat_surfaces_alone() {
+        // A floor, a ceiling and a wall through the middle of the voxel
+        assert_eq!(smooth(Vector3::y(), 0.0), None);
+        assert_eq!(smooth(-Vector3::y(), 0.0), None);
+        assert_eq!(smooth(Vector3::x(), 0.0), None);
+        assert_eq!(smooth(Vector3::new(0.05, 0.0, 1.0), 0.0), None);
+    }
+
+    #[test]
+    fn leaves_floors_and_walls_alone() {
+        // A floor meeting a wall to the north, where the voxels on the edge have a sloped normal
+        let mut grid = VoxelGrid::new(3, 3, 3);
+        for x in 0..3 {
+            for i in 0..3 {
+                let (normal, offset) = match i {
+                    0 => (Vector3::new(0.0, -1.0, -1.0).normalize(), 0.1),
+                    _ => (-Vector3::y(), 0.0),
+                };
+                grid.set(x, 0, i, true);
+                grid.set_surface(x, 0, i, Surface { normal, offset });
+                grid.set(x, i, 0, true);
+                if i > 0 {
+                    let normal = -Vector3::z();
+                    grid.set_surface(
+                        x,
+                        i,
+                        0,
+                        Surface {
+                            normal,
+                            offset: 0.0,
+                        },
+                    );
+                }
+            }
+        }
+        let registry = BlockRegistry::embedded();
+        let stone_bricks = BlockState::new("stone_bricks");
+        assert_eq!(
+            smooth_surface(&mut grid, &stone_bricks, &registry, &version()),
+            0
+        );
+    }
+
+    #[test]
+    fn uses_slabs_for_shallow_slopes() {
+        let slab = smooth(Vector3::new(0.0, 1.0, -0.45), 0.0).unwrap();
+        assert_eq!(
+            slab,
+            BlockState::new("stone_brick_slab").with("type", "bottom")
+        );
+        let slab = smooth(Vector3::new(0.0, -1.0, 0.45), 0.0).unwrap();
+        assert_eq!(slab.get("type"), Some("top"));
+        // A surface at the top of the voxel leaves it full
+        assert_eq!(smooth(Vector3::new(0.0, 1.0, -0.45), 0.5), None);
+    }
+
+    #[test]
+    fn uses_stairs_for_slopes() {
+        // Rising towards the south
+        let stairs = smooth(Vector3::new(0.0, 1.0, -1.0), 0.1).unwrap();
+        assert_eq!(stairs.name, "minecraft:stone_brick_stairs");
+        assert_eq!(stairs.get("facing"), Some("south"));
+        assert_eq!(stairs.get("half"), Some("bottom"));
+        assert_eq!(stairs.get("shape"), Some("straight"));
+    }
+}
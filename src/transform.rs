@@ -0,0 +1,261 @@
+use crate::error::Error;
+use nalgebra::{Isometry3, Matrix3, Point3, Translation3, UnitQuaternion, Vector3};
+use parry3d::shape::TriMesh;
+use std::f32::consts::FRAC_PI_2;
+use std::str::FromStr;
+
+/// One of the three coordinate axes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    /// The index of the axis' component in a vector
+    pub fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+impl FromStr for Axis {
+    type Err = Error;
+
+    /// Accepts "x", "y", "z" as well as COLLADA's "X_UP", "Y_UP" and "Z_UP".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().trim_end_matches("_up") {
+            "x" => Ok(Axis::X),
+            "y" => Ok(Axis::Y),
+            "z" => Ok(Axis::Z),
+            _ => Err(Error::invalid_argument(
+                "axis",
+                format!("{:?} is not an axis", s),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+impl FromStr for Handedness {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "right" | "r" | "rh" => Ok(Handedness::Right),
+            "left" | "l" | "lh" => Ok(Handedness::Left),
+            _ => Err(Error::invalid_argument(
+                "handedness",
+                format!("{:?} is not left or right", s),
+            )),
+        }
+    }
+}
+
+/// The coordinate system a model was authored in.
+/// Minecraft is Y-up and right-handed, which is also the default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoordinateSystem {
+    pub up: Axis,
+    pub handedness: Handedness,
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        Self {
+            up: Axis::Y,
+            handedness: Handedness::Right,
+        }
+    }
+}
+
+impl CoordinateSystem {
+    pub fn new(up: Axis, handedness: Handedness) -> Self {
+        Self { up, handedness }
+    }
+
+    /// The matrix that converts points from this coordinate system into Minecraft's.
+    pub fn to_minecraft(&self) -> Matrix3<f32> {
+        #[rustfmt::skip]
+        let rotation = match self.up {
+            // (x, y, z) -> (-y, x, z)
+            Axis::X => Matrix3::new(
+                0.0, -1.0, 0.0,
+                1.0, 0.0, 0.0,
+                0.0, 0.0, 1.0,
+            ),
+            Axis::Y => Matrix3::identity(),
+            // (x, y, z) -> (x, z, -y), the same conversion Blender uses when exporting to glTF
+            Axis::Z => Matrix3::new(
+                1.0, 0.0, 0.0,
+                0.0, 0.0, 1.0,
+                0.0, -1.0, 0.0,
+            ),
+        };
+        match self.handedness {
+            Handedness::Right => rotation,
+            // Mirroring along Z turns a left-handed system into a right-handed one
+            Handedness::Left => Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, -1.0)) * rotation,
+        }
+    }
+
+    /// Convert the mesh's vertices into Minecraft's coordinate system.
+    pub fn convert_mesh(&self, trimesh: TriMesh) -> TriMesh {
+        if *self == Self::default() {
+            return trimesh;
+        }
+        transform_vertices(trimesh, &self.to_minecraft())
+    }
+}
+
+/// Describes how a model is placed before it is voxelised.
+/// The model is first scaled per-axis, then rotated, then translated.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    /// Rotation of the model around its origin
+    pub rotation: UnitQuaternion<f32>,
+    /// Offset in model units, applied after rotation
+    pub translation: Vector3<f32>,
+    /// Per-axis scale factor, applied before rotation. Allows e.g. squashing a model vertically.
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            rotation: UnitQuaternion::identity(),
+            translation: Vector3::zeros(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    /// Build a rotation from Euler angles in degrees, applied in the order x, y, z.
+    pub fn rotation_from_degrees(x: f32, y: f32, z: f32) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians())
+    }
+
+    /// Turn by 90 degrees around the X axis `x` times, then around Y `y` times and around Z `z` times.
+    pub fn quarter_turns(x: u64, y: u64, z: u64) -> UnitQuaternion<f32> {
+        let turn = |axis, n| UnitQuaternion::from_axis_angle(&axis, FRAC_PI_2 * n as f32);
+        turn(Vector3::z_axis(), z) * turn(Vector3::y_axis(), y) * turn(Vector3::x_axis(), x)
+    }
+
+    /// The rigid part of the transform, i.e. rotation followed by translation.
+    pub fn isometry(&self) -> Isometry3<f32> {
+        Isometry3::from_parts(Translation3::from(self.translation), self.rotation)
+    }
+
+    /// Apply the per-axis scale to the mesh's vertices.
+    /// Since an isometry can't express scaling, the mesh has to be rebuilt.
+    pub fn scale_mesh(&self, trimesh: TriMesh) -> TriMesh {
+        if self.scale == Vector3::new(1.0, 1.0, 1.0) {
+            return trimesh;
+        }
+        transform_vertices(trimesh, &Matrix3::from_diagonal(&self.scale))
+    }
+}
+
+/// Multiply every vertex of the mesh by the matrix.
+/// If the matrix mirrors the mesh, the triangle winding is flipped so that normals keep pointing outwards.
+fn transform_vertices(trimesh: TriMesh, matrix: &Matrix3<f32>) -> TriMesh {
+    let vertices = trimesh
+        .vertices()
+        .iter()
+        .map(|p| Point3::from(matrix * p.coords))
+        .collect();
+    let indices = if matrix.determinant() < 0.0 {
+        trimesh
+            .indices()
+            .iter()
+            .map(|[a, b, c]| [*a, *c, *b])
+            .collect()
+    } else {
+        trimesh.indices().to_vec()
+    };
+    TriMesh::new(vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_vertices_per_axis() {
+        let mesh = TriMesh::new(
+            vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 2.0, 1.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let transform = Transform {
+            scale: Vector3::new(2.0, 0.5, 1.0),
+            ..Default::default()
+        };
+        let mesh = transform.scale_mesh(mesh);
+        assert_eq!(mesh.vertices()[1], Point3::new(2.0, 0.0, 0.0));
+        assert_eq!(mesh.vertices()[2], Point3::new(0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn converts_z_up_to_y_up() {
+        let system = CoordinateSystem::new(Axis::Z, Handedness::Right);
+        let p = system.to_minecraft() * Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(p, Vector3::new(1.0, 3.0, -2.0));
+    }
+
+    #[test]
+    fn mirrors_left_handed_and_flips_winding() {
+        let mesh = TriMesh::new(
+            vec![
+                Point3::new(0.0, 0.0, 1.0),
+                Point3::new(1.0, 0.0, 1.0),
+                Point3::new(0.0, 1.0, 1.0),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let system = CoordinateSystem::new(Axis::Y, Handedness::Left);
+        let mesh = system.convert_mesh(mesh);
+        assert_eq!(mesh.vertices()[0], Point3::new(0.0, 0.0, -1.0));
+        assert_eq!(mesh.indices()[0], [0, 2, 1]);
+    }
+
+    #[test]
+    fn parses_up_axis() {
+        assert_eq!("Z_UP".parse::<Axis>().unwrap(), Axis::Z);
+        assert_eq!("y".parse::<Axis>().unwrap(), Axis::Y);
+        assert!("w".parse::<Axis>().is_err());
+    }
+
+    #[test]
+    fn combines_quarter_turns() {
+        let turned = |x, y, z| Transform::quarter_turns(x, y, z) * Vector3::new(1.0, 2.0, 3.0);
+        assert!((turned(1, 0, 0) - Vector3::new(1.0, -3.0, 2.0)).norm() < 1e-5);
+        // -x then -y: (1, 2, 3) turns to (1, -3, 2) and then to (2, -3, -1)
+        assert!((turned(1, 1, 0) - Vector3::new(2.0, -3.0, -1.0)).norm() < 1e-5);
+        assert!((turned(0, 4, 2) - Vector3::new(-1.0, -2.0, 3.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn rotates_then_translates() {
+        let transform = Transform {
+            rotation: Transform::rotation_from_degrees(0.0, 0.0, 90.0),
+            translation: Vector3::new(10.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let p = transform.isometry() * Point3::new(1.0, 0.0, 0.0);
+        assert!((p - Point3::new(10.0, 1.0, 0.0)).norm() < 1e-5);
+    }
+}
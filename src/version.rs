@@ -0,0 +1,360 @@
+use crate::error::{Error, Result};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The table of version names to DataVersions, compiled into the binary so it works from any directory.
+const EMBEDDED_VERSIONS: &str = include_str!("../minecraft_versions.toml");
+
+/// The first snapshot after the Flattening, which changed block IDs into the namespaced strings we output.
+pub const FLATTENING_DATA_VERSION: i32 = 1451;
+
+/// A Minecraft Java Edition version, identified by its DataVersion.
+/// Versions compare by DataVersion only.
+#[derive(Clone, Debug)]
+pub struct MinecraftVersion {
+    name: Option<String>,
+    data_version: i32,
+}
+
+impl MinecraftVersion {
+    pub fn from_data_version(data_version: i32) -> Self {
+        Self {
+            name: None,
+            data_version,
+        }
+    }
+
+    /// Parse a version name or a DataVersion using the embedded version table.
+    pub fn parse(version: &str) -> Result<Self> {
+        VersionTable::embedded().parse(version)
+    }
+
+    pub fn data_version(&self) -> i32 {
+        self.data_version
+    }
+
+    /// The version's name, if it was looked up by name, e.g. "1.16.2"
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Whether this version uses namespaced block IDs like `minecraft:stone`, i.e. is 1.13 or later.
+    pub fn is_flattened(&self) -> bool {
+        self.data_version >= FLATTENING_DATA_VERSION
+    }
+}
+
+impl PartialEq for MinecraftVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.data_version == other.data_version
+    }
+}
+
+impl Eq for MinecraftVersion {}
+
+impl PartialOrd for MinecraftVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MinecraftVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.data_version.cmp(&other.data_version)
+    }
+}
+
+impl fmt::Display for MinecraftVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} (DataVersion {})", name, self.data_version),
+            None => write!(f, "DataVersion {}", self.data_version),
+        }
+    }
+}
+
+/// How similar a version name has to be to the input to be used as a guess, from 0 to 1.
+const MIN_SIMILARITY: f64 = 0.75;
+
+/// How many close matches to list when a version can't be resolved.
+const MAX_CANDIDATES: usize = 5;
+
+/// A version name broken into its parts, so that e.g. "1.16-pre3" and "1.16 Pre-release 3" are the same version.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum VersionId {
+    /// e.g. "1.16.2"
+    Release(Vec<u32>),
+    /// e.g. "1.16 Pre-release 3" or "1.13-pre3"
+    PreRelease(Vec<u32>, u32),
+    /// e.g. "1.16.2 Release Candidate 1" or "1.16.2-rc1"
+    ReleaseCandidate(Vec<u32>, u32),
+    /// e.g. "20w20a"
+    Snapshot { year: u32, week: u32, letter: char },
+}
+
+impl VersionId {
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        let name = name.strip_prefix("java").unwrap_or(&name).trim_start();
+        let name = name.strip_prefix("snapshot").unwrap_or(name).trim_start();
+
+        if let Some((year, rest)) = name.split_once('w') {
+            let (week, letter) = rest.split_at(rest.len().min(2));
+            let mut letter = letter.chars();
+            return match (year.len(), week.len(), letter.next(), letter.next()) {
+                (2, 2, Some(l), None) if l.is_ascii_lowercase() => Some(VersionId::Snapshot {
+                    year: year.parse().ok()?,
+                    week: week.parse().ok()?,
+                    letter: l,
+                }),
+                _ => None,
+            };
+        }
+
+        let split = name
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(name.len());
+        let (release, rest) = name.split_at(split);
+        let release = release
+            .trim_end_matches('.')
+            .split('.')
+            .map(|n| n.parse().ok())
+            .collect::<Option<Vec<u32>>>()?;
+        if release.len() < 2 {
+            return None;
+        }
+
+        let rest: String = rest
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .collect();
+        if rest.is_empty() {
+            return Some(VersionId::Release(release));
+        }
+        let number = |prefixes: &[&str]| {
+            prefixes
+                .iter()
+                .find_map(|p| rest.strip_prefix(p))
+                .and_then(|n| n.parse().ok())
+        };
+        if let Some(n) = number(&["prerelease", "pre"]) {
+            Some(VersionId::PreRelease(release, n))
+        } else {
+            number(&["releasecandidate", "rc"]).map(|n| VersionId::ReleaseCandidate(release, n))
+        }
+    }
+
+    /// Whether both are the same kind of version, e.g. both snapshots.
+    fn same_kind(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+/// Maps version names to DataVersions.
+#[derive(Clone, Debug)]
+pub struct VersionTable {
+    versions: HashMap<String, i32>,
+    strict: bool,
+}
+
+impl VersionTable {
+    /// The table that ships with threed2vox.
+    pub fn embedded() -> Self {
+        Self::from_toml(EMBEDDED_VERSIONS, "minecraft_versions.toml")
+            .expect("The embedded minecraft_versions.toml is invalid")
+    }
+
+    /// The embedded table, with entries added or replaced by those in the TOML file at `path`.
+    pub fn with_overrides(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let overrides = Self::from_toml(&content, path)?;
+        let mut table = Self::embedded();
+        table.versions.extend(overrides.versions);
+        Ok(table)
+    }
+
+    fn from_toml(content: &str, path: &str) -> Result<Self> {
+        let versions = toml::from_str(content).map_err(|e| Error::parse(path, e))?;
+        Ok(Self {
+            versions,
+            strict: false,
+        })
+    }
+
+    /// In strict mode, names that don't exactly match a version are errors instead of being guessed.
+    /// Different spellings of the same version, like "1.16-pre3" for "1.16 Pre-release 3", are still accepted.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Look up a version name exactly.
+    pub fn get(&self, name: &str) -> Option<MinecraftVersion> {
+        self.versions
+            .get_key_value(name)
+            .map(|(name, &data_version)| MinecraftVersion {
+                name: Some(name.clone()),
+                data_version,
+            })
+    }
+
+    /// Parse either a DataVersion or a version name.
+    ///
+    /// Names are matched exactly, then by their parts, so "1.16 rc1" finds "1.16 Release Candidate 1".
+    /// A name that looks like a release, pre-release, release candidate or snapshot but isn't in the table is an error,
+    /// since the closest name would be a different version. Anything else resolves to the most similar name,
+    /// unless the table is strict, nothing is similar enough, or several names are equally close.
+    pub fn parse(&self, version: &str) -> Result<MinecraftVersion> {
+        if let Ok(data_version) = version.trim().parse() {
+            return Ok(MinecraftVersion::from_data_version(data_version));
+        }
+
+        // First, try to look it up directly, and if it's in there return it
+        if let Some(v) = self.get(version) {
+            return Ok(v);
+        }
+
+        let id = VersionId::parse(version);
+        if let Some(id) = &id {
+            let mut names = self
+                .versions
+                .keys()
+                .filter(|name| VersionId::parse(name).as_ref() == Some(id));
+            if let Some(name) = names.next() {
+                log::info!("Using version '{}' for '{}'.", name, version);
+                return Ok(self.get(name).unwrap());
+            }
+        }
+
+        // Next, rank the version names by how close they are. If the input looked like a
+        // particular kind of version, only compare it to versions of that kind.
+        let lowercase = version.trim().to_lowercase();
+        let mut ranked: Vec<(f64, &String, i32)> = self
+            .versions
+            .iter()
+            .filter(|(name, _)| match (&id, VersionId::parse(name)) {
+                (Some(id), Some(other)) => id.same_kind(&other),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .map(|(name, &v)| {
+                let similarity =
+                    strsim::normalized_damerau_levenshtein(&name.to_lowercase(), &lowercase);
+                (similarity, name, v)
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then_with(|| b.2.cmp(&a.2)));
+        let candidates = ranked
+            .iter()
+            .take(MAX_CANDIDATES)
+            .map(|(_, name, _)| name.to_string())
+            .collect();
+
+        match ranked.as_slice() {
+            [(similarity, name, data_version), rest @ ..]
+                if id.is_none() && *similarity >= MIN_SIMILARITY =>
+            {
+                let tied = rest
+                    .iter()
+                    .any(|(s, _, v)| s == similarity && v != data_version);
+                if self.strict || tied {
+                    return Err(Error::AmbiguousVersion {
+                        version: version.to_string(),
+                        candidates,
+                    });
+                }
+                log::warn!(
+                    "Could not find version '{}', using closest match '{}'. Use --strict-version to disallow this.",
+                    version,
+                    name
+                );
+                Ok(MinecraftVersion {
+                    name: Some(name.to_string()),
+                    data_version: *data_version,
+                })
+            }
+            _ => Err(Error::UnknownVersion {
+                version: version.to_string(),
+                candidates,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(version: &str) -> i32 {
+        MinecraftVersion::parse(version).unwrap().data_version()
+    }
+
+    #[test]
+    fn it_works() {
+        assert_eq!(parse("20w20a"), 2536)
+    }
+
+    #[test]
+    fn prefers_full_match() {
+        assert_eq!(parse("1.16"), 2566)
+    }
+
+    #[test]
+    fn does_partial_match() {
+        assert_eq!(parse("1.13-"), 1519)
+    }
+
+    #[test]
+    fn knows_newer_versions() {
+        assert_eq!(parse("1.20.1"), 3465);
+        assert_eq!(parse("2586"), 2586);
+    }
+
+    #[test]
+    fn recognises_other_spellings() {
+        assert_eq!(parse("1.16-pre3"), 2559);
+        assert_eq!(parse("1.16 rc1"), 2565);
+        assert_eq!(parse("1.13.2 Pre-release 1"), 1629);
+        assert_eq!(parse("Snapshot 20w20a"), 2536);
+        assert_eq!(parse("20w06a"), 2504);
+    }
+
+    #[test]
+    fn rejects_unknown_versions_of_a_known_kind() {
+        match MinecraftVersion::parse("1.61") {
+            Err(Error::UnknownVersion { candidates, .. }) => {
+                assert!(!candidates.is_empty());
+                assert!(candidates.len() <= MAX_CANDIDATES);
+            }
+            other => panic!("Expected an unknown version, got {:?}", other),
+        }
+        assert!(MinecraftVersion::parse("20w99a").is_err());
+        assert!(MinecraftVersion::parse("something else entirely").is_err());
+    }
+
+    #[test]
+    fn guesses_only_when_allowed_and_unambiguous() {
+        assert_eq!(parse("1.16 Pre-relase 3"), 2559);
+        let strict = VersionTable::embedded().strict(true);
+        assert!(matches!(
+            strict.parse("1.16 Pre-relase 3"),
+            Err(Error::AmbiguousVersion { .. })
+        ));
+        assert_eq!(strict.parse("1.16-pre3").unwrap().data_version(), 2559);
+        assert!(matches!(
+            MinecraftVersion::parse("Combat Test 8"),
+            Err(Error::AmbiguousVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn compares_and_checks_flattening() {
+        let old = MinecraftVersion::parse("1.12.2").unwrap();
+        let new = MinecraftVersion::parse("1.13").unwrap();
+        assert!(old < new);
+        assert!(!old.is_flattened());
+        assert!(new.is_flattened());
+        assert_eq!(new.name(), Some("1.13"));
+    }
+}
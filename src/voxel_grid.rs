@@ -1,17 +1,84 @@
-use std::collections::HashMap;
+use crate::blocks::BlockState;
+use crate::readers::reader::MeshPart;
+use nalgebra::Vector3;
+use std::collections::{HashMap, HashSet};
+
+/// The part of the model's surface passing through a voxel, approximated by a plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Surface {
+    /// The average outward normal of the triangles in the voxel
+    pub normal: Vector3<f32>,
+    /// The signed distance from the voxel's centre to the plane along the normal, in voxels
+    pub offset: f32,
+}
+
+impl Surface {
+    /// Which eighths of the voxel are inside the model, i.e. behind the plane.
+    /// Octant `i` is the positive half along X if bit 0 is set, along Y for bit 1 and along Z for bit 2.
+    pub fn occupancy(&self) -> [bool; 8] {
+        let mut octants = [false; 8];
+        for (i, octant) in octants.iter_mut().enumerate() {
+            let centre = Vector3::new(
+                if i & 1 != 0 { 0.25 } else { -0.25 },
+                if i & 2 != 0 { 0.25 } else { -0.25 },
+                if i & 4 != 0 { 0.25 } else { -0.25 },
+            );
+            *octant = centre.dot(&self.normal) < self.offset;
+        }
+        octants
+    }
+}
 
 /// Sparse voxel grid
 pub struct VoxelGrid {
     pub dimensions: (i32, i32, i32),
+    /// Where the centre of voxel (0, 0, 0) is, in the transformed model's coordinates
+    pub origin: Vector3<f32>,
+    /// The length of a voxel in the transformed model's units
+    pub voxel_size: f32,
     map: HashMap<(i32, i32, i32), bool>,
+    /// The surface passing through each voxel
+    surfaces: HashMap<(i32, i32, i32), Surface>,
+    /// Blocks that replace the output's default block in some voxels
+    blocks: HashMap<(i32, i32, i32), BlockState>,
+    /// The named parts of the mesh that was voxelised
+    parts: Vec<MeshPart>,
+    /// The indices of the parts passing through each voxel
+    voxel_parts: HashMap<(i32, i32, i32), Vec<usize>>,
 }
 
 impl VoxelGrid {
     pub fn new(x: i32, y: i32, z: i32) -> Self {
         Self {
             dimensions: (x, y, z),
+            origin: Vector3::zeros(),
+            voxel_size: 1.0,
             map: Default::default(),
+            surfaces: Default::default(),
+            blocks: Default::default(),
+            parts: Default::default(),
+            voxel_parts: Default::default(),
+        }
+    }
+
+    /// A grid of the given dimensions, with the voxels for which `filled` returns true filled in.
+    #[cfg(test)]
+    pub(crate) fn from_fn(
+        dimensions: (i32, i32, i32),
+        filled: impl Fn(i32, i32, i32) -> bool,
+    ) -> Self {
+        let (dx, dy, dz) = dimensions;
+        let mut grid = Self::new(dx, dy, dz);
+        for x in 0..dx {
+            for y in 0..dy {
+                for z in 0..dz {
+                    if filled(x, y, z) {
+                        grid.set(x, y, z, true);
+                    }
+                }
+            }
         }
+        grid
     }
 
     pub fn get(&self, x: i32, y: i32, z: i32) -> &bool {
@@ -20,5 +87,226 @@ impl VoxelGrid {
 
     pub fn set(&mut self, x: i32, y: i32, z: i32, is_set: bool) {
         self.map.insert((x, y, z), is_set);
+        if !is_set {
+            self.blocks.remove(&(x, y, z));
+        }
+    }
+
+    /// The surface in a voxel, if the voxelizer found one.
+    pub fn surface(&self, x: i32, y: i32, z: i32) -> Option<&Surface> {
+        self.surfaces.get(&(x, y, z))
+    }
+
+    /// The surface normal in a voxel, if the voxelizer found one.
+    pub fn normal(&self, x: i32, y: i32, z: i32) -> Option<&Vector3<f32>> {
+        self.surface(x, y, z).map(|s| &s.normal)
+    }
+
+    pub fn set_surface(&mut self, x: i32, y: i32, z: i32, surface: Surface) {
+        self.surfaces.insert((x, y, z), surface);
+    }
+
+    /// The block in a voxel, if it isn't empty and doesn't use the default block.
+    pub fn block(&self, x: i32, y: i32, z: i32) -> Option<&BlockState> {
+        self.blocks.get(&(x, y, z))
+    }
+
+    /// Fill a voxel with a specific block instead of the default one.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: BlockState) {
+        self.map.insert((x, y, z), true);
+        self.blocks.insert((x, y, z), block);
+    }
+
+    /// The named parts of the mesh, if the reader found any.
+    pub fn parts(&self) -> &[MeshPart] {
+        &self.parts
+    }
+
+    pub fn set_parts(&mut self, parts: Vec<MeshPart>) {
+        self.parts = parts;
+    }
+
+    /// The indices into `parts()` of the parts passing through a voxel.
+    pub fn parts_at(&self, x: i32, y: i32, z: i32) -> &[usize] {
+        self.voxel_parts
+            .get(&(x, y, z))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn set_parts_at(&mut self, x: i32, y: i32, z: i32, parts: Vec<usize>) {
+        self.voxel_parts.insert((x, y, z), parts);
+    }
+
+    /// Whether the coordinates are inside the grid's dimensions.
+    pub fn contains(&self, x: i32, y: i32, z: i32) -> bool {
+        let (dx, dy, dz) = self.dimensions;
+        (0..dx).contains(&x) && (0..dy).contains(&y) && (0..dz).contains(&z)
+    }
+
+    /// Grow the grid so that the voxels from `min` to `max` inclusive fit in it, moving everything up if `min` is
+    /// negative. Returns how far the contents moved, which is zero unless the grid grew below its origin.
+    pub fn grow_to_fit(&mut self, min: (i32, i32, i32), max: (i32, i32, i32)) -> (i32, i32, i32) {
+        let shift = ((-min.0).max(0), (-min.1).max(0), (-min.2).max(0));
+        let (dx, dy, dz) = self.dimensions;
+        self.dimensions = (
+            dx.max(max.0 + 1) + shift.0,
+            dy.max(max.1 + 1) + shift.1,
+            dz.max(max.2 + 1) + shift.2,
+        );
+        if shift != (0, 0, 0) {
+            self.move_contents(shift);
+        }
+        shift
+    }
+
+    /// Shrink the grid to the box around its filled voxels, moving them down to start at zero. Returns how far the
+    /// contents moved.
+    pub fn crop_to_filled(&mut self) -> (i32, i32, i32) {
+        let mut filled = self.filled();
+        let first = match filled.next() {
+            Some(first) => first,
+            None => return (0, 0, 0),
+        };
+        let (min, max) = filled.fold((first, first), |(min, max), p| {
+            (
+                (min.0.min(p.0), min.1.min(p.1), min.2.min(p.2)),
+                (max.0.max(p.0), max.1.max(p.1), max.2.max(p.2)),
+            )
+        });
+        let shift = (-min.0, -min.1, -min.2);
+        self.move_contents(shift);
+        self.dimensions = (max.0 - min.0 + 1, max.1 - min.1 + 1, max.2 - min.2 + 1);
+        let (dx, dy, dz) = self.dimensions;
+        let inside = |&(x, y, z): &(i32, i32, i32)| {
+            (0..dx).contains(&x) && (0..dy).contains(&y) && (0..dz).contains(&z)
+        };
+        self.map.retain(|p, _| inside(p));
+        self.surfaces.retain(|p, _| inside(p));
+        self.blocks.retain(|p, _| inside(p));
+        self.voxel_parts.retain(|p, _| inside(p));
+        shift
+    }
+
+    /// Move everything in the grid by `shift` voxels, and the origin the other way so it stays in place in the model.
+    fn move_contents(&mut self, shift: (i32, i32, i32)) {
+        self.origin -=
+            Vector3::new(shift.0 as f32, shift.1 as f32, shift.2 as f32) * self.voxel_size;
+        let moved = |(x, y, z): (i32, i32, i32)| (x + shift.0, y + shift.1, z + shift.2);
+        self.map = self.map.drain().map(|(p, v)| (moved(p), v)).collect();
+        self.surfaces = self.surfaces.drain().map(|(p, v)| (moved(p), v)).collect();
+        self.blocks = self.blocks.drain().map(|(p, v)| (moved(p), v)).collect();
+        self.voxel_parts = self
+            .voxel_parts
+            .drain()
+            .map(|(p, v)| (moved(p), v))
+            .collect();
+    }
+
+    /// Every voxel reachable from outside the grid without passing through a blocked voxel, limited to `y < max_y`.
+    /// The search runs through a one voxel border around the grid, so it reaches every side.
+    pub(crate) fn reachable_from_outside(
+        &self,
+        max_y: i32,
+        blocked: impl Fn(i32, i32, i32) -> bool,
+    ) -> HashSet<(i32, i32, i32)> {
+        let (dx, dy, dz) = self.dimensions;
+        let max_y = max_y.min(dy + 1);
+        let mut reached = HashSet::new();
+        let mut stack = vec![(-1, -1, -1)];
+        reached.insert((-1, -1, -1));
+        while let Some((x, y, z)) = stack.pop() {
+            for &(ox, oy, oz) in &[
+                (-1, 0, 0),
+                (1, 0, 0),
+                (0, -1, 0),
+                (0, 1, 0),
+                (0, 0, -1),
+                (0, 0, 1),
+            ] {
+                let next = (x + ox, y + oy, z + oz);
+                let (nx, ny, nz) = next;
+                let in_border =
+                    (-1..=dx).contains(&nx) && (-1..max_y).contains(&ny) && (-1..=dz).contains(&nz);
+                if in_border && !reached.contains(&next) && !blocked(nx, ny, nz) {
+                    reached.insert(next);
+                    stack.push(next);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Fill every empty voxel that's enclosed by filled ones, turning the voxelised shell of a closed model into a
+    /// solid. Returns how many voxels were filled.
+    pub fn fill_interior(&mut self) -> usize {
+        let (dx, dy, dz) = self.dimensions;
+        // Include the border above the grid, or open-topped shells would be filled
+        let outside = self.reachable_from_outside(dy + 1, |x, y, z| *self.get(x, y, z));
+        let mut count = 0;
+        for x in 0..dx {
+            for y in 0..dy {
+                for z in 0..dz {
+                    if !self.get(x, y, z) && !outside.contains(&(x, y, z)) {
+                        self.set(x, y, z, true);
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    /// The coordinates of every filled voxel, in no particular order.
+    pub fn filled(&self) -> impl Iterator<Item = (i32, i32, i32)> + '_ {
+        self.map
+            .iter()
+            .filter(|(_, &is_set)| is_set)
+            .map(|(&pos, _)| pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_the_inside_of_closed_shells() {
+        let mut grid = VoxelGrid::from_fn((4, 4, 4), |x, y, z| {
+            x.max(y).max(z) < 3 && (x, y, z) != (1, 1, 1)
+        });
+        assert_eq!(grid.fill_interior(), 1);
+        grid.set(1, 2, 1, false);
+        grid.set(1, 1, 1, false);
+        assert_eq!(grid.fill_interior(), 0);
+
+        // Open at the top of the grid
+        grid.dimensions = (3, 3, 3);
+        assert_eq!(grid.fill_interior(), 0);
+    }
+
+    #[test]
+    fn moves_the_origin_when_growing_below_it() {
+        let mut grid = VoxelGrid::new(2, 2, 2);
+        grid.voxel_size = 0.5;
+        grid.set_block(0, 0, 0, BlockState::new("glass"));
+        assert_eq!(grid.grow_to_fit((-2, 0, 0), (1, 3, 1)), (2, 0, 0));
+        assert_eq!(grid.dimensions, (4, 4, 2));
+        assert_eq!(grid.origin, Vector3::new(-1.0, 0.0, 0.0));
+        assert_eq!(grid.block(2, 0, 0), Some(&BlockState::new("glass")));
+    }
+
+    #[test]
+    fn crops_to_the_filled_voxels() {
+        let mut grid = VoxelGrid::new(4, 4, 4);
+        grid.voxel_size = 0.5;
+        grid.set(1, 2, 1, true);
+        grid.set_block(2, 2, 3, BlockState::new("glass"));
+        grid.set(3, 3, 3, false);
+        assert_eq!(grid.crop_to_filled(), (-1, -2, -1));
+        assert_eq!(grid.dimensions, (2, 1, 3));
+        assert_eq!(grid.origin, Vector3::new(0.5, 1.0, 0.5));
+        assert_eq!(grid.block(1, 0, 2), Some(&BlockState::new("glass")));
+        assert_eq!(grid.filled().count(), 2);
     }
 }
@@ -0,0 +1,534 @@
+//! Turns meshes into voxel grids, independently of how the mesh was loaded or how the grid is written.
+//!
+//! ```no_run
+//! use threed2vox::blocks::BlockState;
+//! use threed2vox::nbtifier::{NBTIfy, OutputOptions, SchematicV2};
+//! use threed2vox::readers::{reader::Reader, ObjReader};
+//! use threed2vox::version::MinecraftVersion;
+//! use threed2vox::voxelizer::{VoxelOption, Voxelizer};
+//!
+//! let trimesh = ObjReader.load("models/teapot.obj").unwrap();
+//! let grid = Voxelizer::new()
+//!     .voxel_size(VoxelOption::MeshSize(20.0))
+//!     .voxelize(trimesh)
+//!     .unwrap();
+//! let version = MinecraftVersion::parse("1.16").unwrap();
+//! let options = OutputOptions::new(version, "teapot", BlockState::new("stone"));
+//! let blob = SchematicV2.convert(&grid, &options).unwrap();
+//! ```
+
+use crate::error::{Error, Result};
+use crate::progress::{CancellationToken, Progress, ProgressCallback, Reporter};
+use crate::readers::reader::MeshPart;
+use crate::transform::{Axis, CoordinateSystem, Transform};
+use crate::voxel_grid::{Surface, VoxelGrid};
+use nalgebra::{Point3, Vector3};
+use parry3d::na::Isometry3;
+use parry3d::query::{
+    ContactManifold, ContactManifoldsWorkspace, DefaultQueryDispatcher, PersistentQueryDispatcher,
+};
+use parry3d::shape::{Cuboid, TriMesh};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug)]
+pub enum VoxelOption {
+    /// Explicitly define a voxel size
+    VoxelSize(f32),
+    /// Define the voxel length of the longest axis of the model's AABB.
+    MeshSize(f32),
+    /// Define the voxel length of a specific axis of the model's AABB, e.g. the height.
+    AxisSize(Axis, f32),
+    /// Scale the model to the largest size that fits within the given number of blocks on each axis.
+    FitBox(Vector3<f32>),
+    /// Scale the model to real-world size, given the number of blocks per metre.
+    /// Requires knowing how many metres a model unit is.
+    RealScale(f32),
+}
+
+impl VoxelOption {
+    /// Work out the size of a voxel in model units.
+    /// # Arguments
+    /// * `extents`: The extents of the model's AABB, in model units
+    /// * `metres_per_unit`: How many metres one model unit is. Only called for `RealScale`.
+    pub fn voxel_size(&self, extents: &Vector3<f32>, metres_per_unit: impl FnOnce() -> f32) -> f32 {
+        match *self {
+            VoxelOption::VoxelSize(s) => s,
+            VoxelOption::MeshSize(s) => extents.max() / s,
+            VoxelOption::AxisSize(axis, s) => extents[axis.index()] / s,
+            VoxelOption::FitBox(size) => extents.component_div(&size).max(),
+            VoxelOption::RealScale(blocks_per_metre) => {
+                1.0 / (metres_per_unit() * blocks_per_metre)
+            }
+        }
+    }
+}
+
+/// Builder for voxelising meshes.
+/// The mesh is converted into Minecraft's coordinate system, transformed, and then sampled into a grid.
+#[derive(Clone)]
+pub struct Voxelizer {
+    voxel_size: VoxelOption,
+    transform: Transform,
+    coordinate_system: CoordinateSystem,
+    metres_per_unit: Option<f32>,
+    max_dimension: i32,
+    parts: Vec<MeshPart>,
+    progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl Default for Voxelizer {
+    fn default() -> Self {
+        Self {
+            voxel_size: VoxelOption::VoxelSize(1.0),
+            transform: Default::default(),
+            coordinate_system: Default::default(),
+            metres_per_unit: None,
+            // Schematics store their dimensions as unsigned shorts
+            max_dimension: u16::MAX as i32,
+            parts: Vec::new(),
+            progress: None,
+            cancellation: None,
+        }
+    }
+}
+
+impl Voxelizer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// How large a voxel is. Defaults to 1 model unit per block.
+    pub fn voxel_size(mut self, voxel_size: VoxelOption) -> Self {
+        self.voxel_size = voxel_size;
+        self
+    }
+
+    /// Rotation, translation and scale to apply to the mesh.
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// The coordinate system the mesh is in. Defaults to Minecraft's, i.e. Y-up and right-handed.
+    pub fn coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.coordinate_system = coordinate_system;
+        self
+    }
+
+    /// How many metres one model unit is. Only used by `VoxelOption::RealScale`, and assumed to be 1 if unset.
+    pub fn metres_per_unit(mut self, metres_per_unit: f32) -> Self {
+        self.metres_per_unit = Some(metres_per_unit);
+        self
+    }
+
+    /// The largest number of blocks allowed along any axis. Larger grids fail with `Error::GridTooLarge`.
+    pub fn max_dimension(mut self, max_dimension: i32) -> Self {
+        self.max_dimension = max_dimension;
+        self
+    }
+
+    /// The named parts of the mesh, as returned by `Reader::load_parts`.
+    /// The grid records which parts pass through each voxel, e.g. to fill a part named "water" with water.
+    pub fn parts(mut self, parts: Vec<MeshPart>) -> Self {
+        self.parts = parts;
+        self
+    }
+
+    /// Call `callback` as the conversion progresses, e.g. to drive a progress bar.
+    pub fn on_progress(mut self, callback: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Abort the conversion once `token` is cancelled. The token is checked between slabs of the grid.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Voxelise the mesh, marking every voxel the mesh's surface passes through.
+    /// Fails if the voxel size is invalid, the grid would be too large, or the conversion is cancelled.
+    pub fn voxelize(&self, trimesh: TriMesh) -> Result<VoxelGrid> {
+        let trimesh = self.coordinate_system.convert_mesh(trimesh);
+        let trimesh = self.transform.scale_mesh(trimesh);
+
+        let mut trimesh_transform = self.transform.isometry();
+        let aabb = trimesh.aabb(&trimesh_transform);
+
+        let voxel_size = self.voxel_size.voxel_size(&aabb.extents(), || {
+            self.metres_per_unit.unwrap_or_else(|| {
+                log::warn!("The units of the model are unknown, assuming metres.");
+                1.0
+            })
+        });
+        if !(voxel_size.is_finite() && voxel_size > 0.0) {
+            return Err(Error::invalid_argument(
+                "size",
+                format!("The voxel size must be positive, but is {}", voxel_size),
+            ));
+        }
+
+        // Move the model so that its AABB starts inside the first voxel. The origin is snapped to
+        // whole voxels, so the translation still decides where the model sits within a voxel.
+        let origin = (aabb.mins.coords / voxel_size).map(f32::floor) * voxel_size;
+        trimesh_transform.translation.vector -= origin;
+
+        // Determine the voxel grid size
+        let maxs = trimesh.aabb(&trimesh_transform).maxs;
+        let x = f32::ceil(maxs.x / voxel_size) as i32 + 1;
+        let y = f32::ceil(maxs.y / voxel_size) as i32 + 1;
+        let z = f32::ceil(maxs.z / voxel_size) as i32 + 1;
+        if x > self.max_dimension || y > self.max_dimension || z > self.max_dimension {
+            return Err(Error::GridTooLarge {
+                x,
+                y,
+                z,
+                max: self.max_dimension,
+            });
+        }
+
+        let mut grid = VoxelGrid::new(x, y, z);
+        grid.voxel_size = voxel_size;
+        grid.origin = origin - Vector3::repeat(voxel_size / 2.0);
+        grid.set_parts(self.parts.clone());
+
+        // Iterate over voxels and do collision tests
+        log::info!(
+            "Dimensions of the model are {}x{}x{}. Starting conversion.",
+            x,
+            y,
+            z
+        );
+
+        let reporter = Reporter::new(
+            "Voxelising",
+            x as usize,
+            self.progress.as_ref(),
+            self.cancellation.as_ref(),
+        );
+        let results = match cfg!(feature = "sequential") {
+            true => do_collision_seq(
+                (x, y, z),
+                voxel_size,
+                &trimesh,
+                &trimesh_transform,
+                &reporter,
+            ),
+            false => do_collision_par(
+                (x, y, z),
+                voxel_size,
+                &trimesh,
+                &trimesh_transform,
+                &reporter,
+            ),
+        };
+        if reporter.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        log::info!("Filled {} voxels.", results.len());
+
+        // Fit a plane to the triangles in each voxel, e.g. for orienting blocks
+        for ((i, j, k), triangles) in results {
+            grid.set(i, j, k, true);
+            if !self.parts.is_empty() {
+                let mut parts: Vec<usize> = triangles
+                    .iter()
+                    .filter_map(|t| self.parts.iter().position(|p| p.triangles.contains(t)))
+                    .collect();
+                parts.sort_unstable();
+                parts.dedup();
+                grid.set_parts_at(i, j, k, parts);
+            }
+            let centre = Point3::new(i as f32, j as f32, k as f32) * voxel_size
+                - Vector3::repeat(voxel_size / 2.0);
+            let triangles: Vec<_> = triangles
+                .iter()
+                .map(|&t| trimesh.triangle(t).transformed(&trimesh_transform))
+                .collect();
+            let normal: Vector3<f32> = triangles.iter().map(|t| t.scaled_normal()).sum();
+            if let Some(normal) = normal.try_normalize(f32::EPSILON) {
+                // The area-weighted distance of the triangles' planes from the centre
+                let (distance, area) = triangles.iter().fold((0.0, 0.0), |(d, a), t| {
+                    let area = t.scaled_normal().norm();
+                    (d + area * (t.a - centre).dot(&normal), a + area)
+                });
+                let surface = Surface {
+                    normal,
+                    offset: distance / area / voxel_size,
+                };
+                grid.set_surface(i, j, k, surface);
+            }
+        }
+
+        Ok(grid)
+    }
+}
+
+/// A voxel the mesh passes through, and the indices of the triangles that intersect it.
+type Hit = ((i32, i32, i32), Vec<u32>);
+
+/// The inner part of do_collision_*
+fn actually_do_collision(
+    xyz: (i32, i32, i32),
+    voxel_size: f32,
+    voxel: &Cuboid,
+    trimesh: &TriMesh,
+    pos: &Isometry3<f32>,
+    manifold: &mut Vec<ContactManifold<(), ()>>,
+    workspace: &mut Option<ContactManifoldsWorkspace>,
+) -> Option<Hit> {
+    let (x, y, z) = xyz;
+    let voxel_half = voxel_size / 2.0;
+
+    let transform = Isometry3::translation(
+        (x as f32) * voxel_size - voxel_half,
+        (y as f32) * voxel_size - voxel_half,
+        (z as f32) * voxel_size - voxel_half,
+    );
+
+    let dispatch = DefaultQueryDispatcher;
+
+    // See source code for contact_shape_shape for why
+    let pos12 = transform.inv_mul(pos);
+
+    dispatch
+        .contact_manifolds(&pos12, voxel, trimesh, 0.0, manifold, workspace)
+        .unwrap();
+
+    // Manifolds are kept for every triangle whose bounding box overlaps the voxel,
+    // but only those with contact points actually touch it.
+    let triangles: Vec<u32> = manifold
+        .iter()
+        .filter(|m| !m.points.is_empty())
+        .map(|m| m.subshape2)
+        .collect();
+    match triangles.len() {
+        0 => None,
+        _ => Some(((x, y, z), triangles)),
+    }
+}
+
+fn do_collision_par(
+    xyz: (i32, i32, i32),
+    voxel_size: f32,
+    trimesh: &TriMesh,
+    pos: &Isometry3<f32>,
+    reporter: &Reporter,
+) -> Vec<Hit> {
+    let (x, y, z) = xyz;
+    let voxel_half = voxel_size / 2.0;
+    let voxel = Cuboid::new(Vector3::new(voxel_half, voxel_half, voxel_half));
+
+    (0..x)
+        .into_par_iter()
+        .map(|i| {
+            // Skip the remaining slabs once cancelled
+            if reporter.is_cancelled() {
+                return Vec::new();
+            }
+            let result = (0..y)
+                .into_par_iter()
+                .map(|j| {
+                    let mut range = (0..z).peekable();
+                    let mut z_range = Vec::new();
+                    while range.peek().is_some() {
+                        let chunk: Vec<_> = range.by_ref().take(100).collect();
+                        z_range.push(chunk);
+                    }
+                    z_range
+                        .into_par_iter()
+                        .map(|ks| {
+                            let mut manifolds = Vec::new();
+                            let mut workspace = None;
+                            let mut output = Vec::new();
+                            for k in ks {
+                                if let Some(o) = actually_do_collision(
+                                    (i, j, k),
+                                    voxel_size,
+                                    &voxel,
+                                    trimesh,
+                                    pos,
+                                    &mut manifolds,
+                                    &mut workspace,
+                                ) {
+                                    output.push(o);
+                                }
+                            }
+                            output
+                        })
+                        .flatten()
+                        .collect::<Vec<_>>()
+                })
+                .flatten()
+                .collect::<Vec<_>>();
+            reporter.step();
+            result
+        })
+        .flatten()
+        .collect()
+}
+
+fn do_collision_seq(
+    xyz: (i32, i32, i32),
+    voxel_size: f32,
+    trimesh: &TriMesh,
+    pos: &Isometry3<f32>,
+    reporter: &Reporter,
+) -> Vec<Hit> {
+    let (x, y, z) = xyz;
+    let voxel_half = voxel_size / 2.0;
+    let voxel = Cuboid::new(Vector3::new(voxel_half, voxel_half, voxel_half));
+
+    let mut manifold = Vec::new();
+    let mut workspace = None;
+
+    (0..x)
+        .take_while(|_| !reporter.is_cancelled())
+        .flat_map(|i| {
+            let result = (0..y)
+                .flat_map(|j| {
+                    (0..z)
+                        .filter_map(|k| {
+                            actually_do_collision(
+                                (i, j, k),
+                                voxel_size,
+                                &voxel,
+                                trimesh,
+                                pos,
+                                &mut manifold,
+                                &mut workspace,
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            reporter.step();
+            result
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_voxels_by_axis_and_box() {
+        let extents = Vector3::new(10.0, 20.0, 5.0);
+        let unused = || unreachable!();
+        assert_eq!(
+            VoxelOption::MeshSize(10.0).voxel_size(&extents, unused),
+            2.0
+        );
+        assert_eq!(
+            VoxelOption::AxisSize(Axis::Z, 10.0).voxel_size(&extents, unused),
+            0.5
+        );
+        // The Y axis is the tightest fit here
+        let fit = VoxelOption::FitBox(Vector3::new(10.0, 10.0, 10.0));
+        assert_eq!(fit.voxel_size(&extents, unused), 2.0);
+    }
+
+    #[test]
+    fn sizes_voxels_by_real_units() {
+        let extents = Vector3::new(1.0, 1.0, 1.0);
+        // A model in centimetres at 2 blocks per metre needs 50 units per block
+        let size = VoxelOption::RealScale(2.0).voxel_size(&extents, || 0.01);
+        assert!((size - 50.0).abs() < 1e-3);
+    }
+
+    fn triangle() -> TriMesh {
+        TriMesh::new(
+            vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(4.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, 4.0),
+            ],
+            vec![[0, 1, 2]],
+        )
+    }
+
+    #[test]
+    fn reports_progress() {
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        Voxelizer::new()
+            .on_progress(move |p| sink.lock().unwrap().push(p))
+            .voxelize(triangle())
+            .unwrap();
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.first().unwrap().fraction, 0.0);
+        assert_eq!(reports.last().unwrap().fraction, 1.0);
+        assert!(reports.iter().all(|p| p.stage == "Voxelising"));
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = Voxelizer::new()
+            .cancellation_token(token)
+            .voxelize(triangle());
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn rejects_huge_grids() {
+        let result = Voxelizer::new()
+            .voxel_size(VoxelOption::VoxelSize(0.01))
+            .max_dimension(100)
+            .voxelize(triangle());
+        assert!(matches!(result, Err(Error::GridTooLarge { max: 100, .. })));
+    }
+
+    #[test]
+    fn voxelizes_a_triangle() {
+        let grid = Voxelizer::new().voxelize(triangle()).unwrap();
+        assert_eq!(grid.dimensions.1, 1);
+        assert!(*grid.get(1, 0, 1));
+        // Inside the triangle's bounding box, but beyond its hypotenuse
+        assert!(!*grid.get(4, 0, 4));
+        assert_eq!(grid.normal(1, 0, 1), Some(&-Vector3::y()));
+        // The triangle lies on the top face of the voxels, and faces down, so they're all outside
+        let surface = grid.surface(1, 0, 1).unwrap();
+        assert_eq!(surface.offset, -0.5);
+        assert_eq!(surface.occupancy(), [false; 8]);
+    }
+
+    #[test]
+    fn fills_only_voxels_the_mesh_touches() {
+        // A slope rising along Z, whose bounding box is a cube
+        let slope = TriMesh::new(
+            vec![
+                Point3::new(0.3, 0.3, 0.3),
+                Point3::new(7.7, 0.3, 0.3),
+                Point3::new(0.3, 7.7, 7.7),
+            ],
+            vec![[0, 1, 2]],
+        );
+        let grid = Voxelizer::new().voxelize(slope).unwrap();
+        assert!(*grid.get(2, 2, 2));
+        // Inside the bounding box, but away from the slope
+        assert!(!*grid.get(2, 2, 6));
+        assert!(!*grid.get(2, 6, 2));
+    }
+
+    #[test]
+    fn places_grids_in_a_shared_frame() {
+        let moved = TriMesh::new(
+            triangle()
+                .vertices()
+                .iter()
+                .map(|p| p + Vector3::new(3.0, 2.0, 0.0))
+                .collect(),
+            vec![[0, 1, 2]],
+        );
+        let a = Voxelizer::new().voxelize(triangle()).unwrap();
+        let b = Voxelizer::new().voxelize(moved).unwrap();
+        assert_eq!(a.voxel_size, 1.0);
+        assert_eq!(b.origin - a.origin, Vector3::new(3.0, 2.0, 0.0));
+    }
+}
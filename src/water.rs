@@ -0,0 +1,212 @@
+//! Fills parts of the model with water, e.g. the sea around a ship or the basin of a fountain,
+//! and waterlogs the blocks that sit in it.
+
+use crate::blocks::{BlockRegistry, BlockState};
+use crate::voxel_grid::VoxelGrid;
+
+/// The directions water can reach a block from: the four sides and above.
+const WATER_NEIGHBOURS: [(i32, i32, i32); 5] =
+    [(-1, 0, 0), (1, 0, 0), (0, 0, -1), (0, 0, 1), (0, 1, 0)];
+
+/// A full, still water source.
+pub fn water() -> BlockState {
+    BlockState::new("water").with("level", "0")
+}
+
+pub(crate) fn is_water(block: &BlockState) -> bool {
+    block.name == "minecraft:water"
+}
+
+/// Fill voxels with water and return how many were filled.
+/// * Voxels that only parts named one of `parts` pass through become water, as does everything those parts enclose.
+///   A part matches if its own name or its material's name does, ignoring case.
+/// * With a `level`, empty voxels below that Y coordinate become water if they're enclosed on the sides and
+///   below, e.g. inside a ship's hull. The voxelised shell of a model is hollow, so this fills closed models too.
+pub fn fill_water(grid: &mut VoxelGrid, parts: &[String], level: Option<i32>) -> usize {
+    let water_parts: Vec<usize> = grid
+        .parts()
+        .iter()
+        .enumerate()
+        .filter(|(_, part)| parts.iter().any(|name| part.is_named(name)))
+        .map(|(i, _)| i)
+        .collect();
+    for name in parts {
+        if !water_parts.iter().any(|&i| grid.parts()[i].is_named(name)) {
+            log::warn!("No part or material of the model is named {:?}.", name);
+        }
+    }
+
+    let (dx, dy, dz) = grid.dimensions;
+    let mut water_voxels = Vec::new();
+    if !water_parts.is_empty() {
+        let touches_water = |x, y, z| {
+            grid.parts_at(x, y, z)
+                .iter()
+                .any(|i| water_parts.contains(i))
+        };
+        // Where water meets the rest of the model, the model wins
+        water_voxels.extend(grid.filled().filter(|&(x, y, z)| {
+            grid.parts_at(x, y, z)
+                .iter()
+                .all(|i| water_parts.contains(i))
+                && touches_water(x, y, z)
+        }));
+        let outside = grid.reachable_from_outside(dy + 1, touches_water);
+        for x in 0..dx {
+            for y in 0..dy {
+                for z in 0..dz {
+                    if !grid.get(x, y, z) && !outside.contains(&(x, y, z)) {
+                        water_voxels.push((x, y, z));
+                    }
+                }
+            }
+        }
+    }
+    if let Some(level) = level {
+        let outside = grid.reachable_from_outside(level, |x, y, z| *grid.get(x, y, z));
+        for x in 0..dx {
+            for y in 0..level.min(dy) {
+                for z in 0..dz {
+                    if !grid.get(x, y, z) && !outside.contains(&(x, y, z)) {
+                        water_voxels.push((x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    water_voxels.sort_unstable();
+    water_voxels.dedup();
+    for &(x, y, z) in &water_voxels {
+        grid.set_block(x, y, z, water());
+    }
+    water_voxels.len()
+}
+
+/// Set `waterlogged=true` on blocks that can hold water, like stairs, slabs and fences, if water is beside or above
+/// them, using `default` where the grid doesn't set a block. Blocks that already set `waterlogged` are left alone.
+/// Returns how many voxels were changed.
+pub fn waterlog_blocks(
+    grid: &mut VoxelGrid,
+    default: &BlockState,
+    registry: &BlockRegistry,
+) -> usize {
+    let in_water = |x: i32, y: i32, z: i32| {
+        WATER_NEIGHBOURS
+            .iter()
+            .any(|(ox, oy, oz)| grid.block(x + ox, y + oy, z + oz).is_some_and(is_water))
+    };
+    let changes: Vec<_> = grid
+        .filled()
+        .filter_map(|(x, y, z)| {
+            let block = grid.block(x, y, z).unwrap_or(default);
+            let can_hold_water = registry
+                .get(&block.name)
+                .is_some_and(|info| info.properties.contains_key("waterlogged"));
+            if can_hold_water && block.get("waterlogged").is_none() && in_water(x, y, z) {
+                Some(((x, y, z), block.clone().with("waterlogged", "true")))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let count = changes.len();
+    for ((x, y, z), block) in changes {
+        grid.set_block(x, y, z, block);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::readers::reader::MeshPart;
+
+    /// A 5x4x5 box open at the top, with walls made of part 0.
+    fn basin() -> VoxelGrid {
+        let mut grid = VoxelGrid::from_fn((5, 4, 5), |x, y, z| {
+            y == 0 || x == 0 || x == 4 || z == 0 || z == 4
+        });
+        grid.set_parts(vec![MeshPart {
+            name: "Hull".to_string(),
+            material: Some("Wood".to_string()),
+            triangles: 0..1,
+        }]);
+        for (x, y, z) in grid.filled().collect::<Vec<_>>() {
+            grid.set_parts_at(x, y, z, vec![0]);
+        }
+        grid
+    }
+
+    #[test]
+    fn fills_below_the_water_level_inside_hulls() {
+        let mut grid = basin();
+        assert_eq!(fill_water(&mut grid, &[], Some(3)), 9 * 2);
+        assert_eq!(grid.block(2, 2, 2), Some(&water()));
+        assert_eq!(grid.block(2, 3, 2), None);
+        // The sea outside the hull isn't filled
+        let mut grid = VoxelGrid::new(3, 3, 3);
+        grid.set(1, 0, 1, true);
+        assert_eq!(fill_water(&mut grid, &[], Some(2)), 0);
+    }
+
+    #[test]
+    fn fills_parts_named_water() {
+        // A closed box of water, with one voxel shared with the hull
+        let mut grid = VoxelGrid::from_fn((3, 3, 3), |x, y, z| (x, y, z) != (1, 1, 1));
+        grid.set_parts(vec![
+            MeshPart {
+                name: "Pool".to_string(),
+                material: Some("WATER".to_string()),
+                triangles: 0..12,
+            },
+            MeshPart {
+                name: "Hull".to_string(),
+                material: None,
+                triangles: 12..24,
+            },
+        ]);
+        for (x, y, z) in grid.filled().collect::<Vec<_>>() {
+            grid.set_parts_at(x, y, z, vec![0]);
+        }
+        grid.set_parts_at(0, 0, 0, vec![0, 1]);
+
+        assert_eq!(fill_water(&mut grid, &["water".to_string()], None), 26);
+        assert_eq!(grid.block(1, 1, 1), Some(&water()));
+        assert_eq!(grid.block(0, 0, 0), None);
+
+        // Water open at the top of the grid doesn't enclose anything
+        let mut grid = basin();
+        assert_eq!(
+            fill_water(&mut grid, &["wood".to_string()], None),
+            25 + 16 * 3
+        );
+        assert_eq!(grid.block(2, 2, 2), None);
+    }
+
+    #[test]
+    fn waterlogs_blocks_in_water() {
+        let registry = BlockRegistry::embedded();
+        let mut grid = basin();
+        let slab = BlockState::new("oak_slab").with("type", "bottom");
+        grid.set_block(2, 1, 2, slab.clone());
+        fill_water(&mut grid, &[], Some(3));
+
+        assert_eq!(
+            waterlog_blocks(&mut grid, &BlockState::new("stone"), &registry),
+            1
+        );
+        assert_eq!(grid.block(2, 1, 2), Some(&slab.with("waterlogged", "true")));
+        // The walls and floor are fences, but the top row is above the water, and the slab covers the middle of the floor
+        let fence = BlockState::new("oak_fence");
+        let logged = waterlog_blocks(&mut grid, &fence, &registry);
+        assert_eq!(logged, 4 * 3 * 2 + 8);
+        assert_eq!(
+            grid.block(1, 0, 1),
+            Some(&fence.with("waterlogged", "true"))
+        );
+        assert_eq!(grid.block(0, 3, 2), None);
+        assert_eq!(grid.block(2, 0, 2), None);
+    }
+}